@@ -6,12 +6,64 @@
 //! - A `cancel` function for the initializer.
 //! - Explicit on-chain `EscrowStatus` for clear state management.
 //! - Events for all state transitions, allowing for easy off-chain monitoring.
+
+// The IDL instructions `#[program]` generates (`__private::__idl`, a sibling of
+// the program module that this crate cannot annotate) still call the
+// `AccountInfo::realloc` deprecated in solana-program 2.3. The crate root is the
+// only item that encloses them, and they only exist without `no-idl`.
+#![cfg_attr(not(feature = "no-idl"), allow(deprecated))]
+
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token::{self, CloseAccount, Mint, Token, TokenAccount, TransferChecked};
 use anchor_lang::solana_program::clock::Clock;
+use anchor_lang::solana_program::sysvar::instructions::{self as sysvar_instructions, load_instruction_at_checked};
+use anchor_lang::system_program;
+use std::collections::BTreeSet;
 
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 
+/// Time (in seconds) a named arbiter has to accept the role.
+pub const ARBITER_ACCEPTANCE_PERIOD: i64 = 3 * 24 * 60 * 60;
+
+/// Time (in seconds) the parties have to replace a resigned arbiter before
+/// the backup arbiter may take over.
+pub const ARBITER_REPLACEMENT_PERIOD: i64 = 7 * 24 * 60 * 60;
+
+/// Time (in seconds) past the timeout after which a recovery key may refund
+/// on behalf of an inactive initializer.
+pub const RECOVERY_DELAY: i64 = 90 * 24 * 60 * 60;
+
+/// Time (in seconds) before the timeout during which `emit_timeout_warning`
+/// may be called on escrows from before each escrow recorded its own
+/// `timeout_warning_window`.
+pub const TIMEOUT_WARNING_WINDOW: i64 = 24 * 60 * 60;
+
+/// Shortest review window an escrow may give the initializer after the
+/// recipient marks the work delivered.
+pub const MIN_DISPUTE_WINDOW: i64 = 60 * 60;
+
+/// Longest review window an escrow may give the initializer after the
+/// recipient marks the work delivered.
+pub const MAX_DISPUTE_WINDOW: i64 = 30 * 24 * 60 * 60;
+
+/// Longest handle (in bytes) a party can register with `set_handle`.
+pub const MAX_HANDLE_LEN: usize = 32;
+
+/// Denominator for amounts expressed in basis points.
+pub const MAX_BPS: u16 = 10_000;
+
+/// Accounts `resolve_batch` expects per escrow: those of `ResolveByArbiter`.
+pub const RESOLVE_BATCH_GROUP_LEN: usize = 9;
+
+/// Programs other than this one that a guarded settlement may share its
+/// transaction with: the compute budget program and the associated token
+/// program, which clients use to create the payout account.
+pub const SETTLEMENT_GUARD_ALLOWLIST: [Pubkey; 2] = [
+    pubkey!("ComputeBudget111111111111111111111111111111"),
+    anchor_spl::associated_token::ID,
+];
+
 #[program]
 pub mod escrow {
     use super::*;
@@ -23,394 +75,5206 @@ pub mod escrow {
     /// * `ctx` - The context of accounts for the instruction.
     /// * `amount` - The amount of tokens to be held in escrow.
     /// * `timeout` - The duration (in seconds) after which the escrow can be refunded.
-    pub fn initialize(ctx: Context<Initialize>, amount: u64, timeout: i64) -> Result<()> {
-        require!(amount > 0, EscrowError::InvalidAmount);
+    /// * `terms_hash` - Hash of the off-chain agreement the escrow was created under.
+    /// * `external_id` - Caller-defined reference, such as a purchase order number.
+    /// * `invoice_hash` - Hash of the invoice the escrow settles.
+    /// * `order_seeded` - Whether to derive the escrow address from `external_id`, as
+    ///   `[b"escrow", initializer, external_id.to_le_bytes()]`, instead of the recipient.
+    /// * `release_not_before` - Unix timestamp before which the funds cannot be released
+    ///   to the recipient, or 0 for none.
+    /// * `reject_freezable` - Whether to fail, rather than only set `mint_freezable`,
+    ///   when the mint has a freeze authority.
+    /// * `timeout_warning_window` - Time (in seconds) before the timeout from which
+    ///   `emit_timeout_warning` may be called. Must be positive and at most `timeout`.
+    /// * `dispute_window` - Time (in seconds) the initializer has to review the work
+    ///   after `mark_delivered` before the recipient may withdraw, or 0 for none.
+    ///   Must lie between `MIN_DISPUTE_WINDOW` and `MAX_DISPUTE_WINDOW`.
+    /// * `required_qualifications` - Qualification bits an arbiter must be registered
+    ///   with to `claim_case`, when no arbiter is named. Otherwise 0.
+    /// * `max_arbiter_fee_bps` - Highest fee an arbiter claiming the case may charge,
+    ///   when no arbiter is named. Otherwise 0.
+    ///
+    /// Passing a `template` applies its dispute window, retainage and arbiter fee
+    /// cap; the arguments it replaces must then be 0.
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        amount: u64,
+        timeout: i64,
+        terms_hash: [u8; 32],
+        external_id: u64,
+        invoice_hash: [u8; 32],
+        order_seeded: bool,
+        release_not_before: i64,
+        reject_freezable: bool,
+        timeout_warning_window: i64,
+        dispute_window: i64,
+        required_qualifications: u32,
+        max_arbiter_fee_bps: u16,
+    ) -> Result<()> {
+        require!(release_not_before >= 0, EscrowError::InvalidReleaseDate);
+        require!(
+            !reject_freezable || ctx.accounts.mint.freeze_authority.is_none(),
+            EscrowError::FreezableMint
+        );
         let initializer = &ctx.accounts.initializer;
         let recipient = &ctx.accounts.recipient;
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let arbiter = ctx.accounts.arbiter.as_ref().map(|arbiter| arbiter.key());
+        escrow_state.record_terms(
+            ctx.accounts.mint.key(),
+            initializer.key(),
+            recipient.key(),
+            arbiter.unwrap_or_default(),
+            amount,
+            timeout,
+            terms_hash,
+            timeout_warning_window,
+            dispute_window,
+        )?;
+        escrow_state.record_listing(
+            arbiter.is_some(),
+            ctx.accounts.arbiter_registrar.as_ref().map(|registrar| registrar.key()),
+            required_qualifications,
+            max_arbiter_fee_bps,
+        )?;
+        escrow_state.external_id = external_id;
+        escrow_state.invoice_hash = invoice_hash;
+        escrow_state.order_seeded = order_seeded;
+        escrow_state.release_not_before = release_not_before;
+        if let Some(cosigner) = &ctx.accounts.withdraw_cosigner {
+            escrow_state.withdraw_cosigner = cosigner.key();
+        }
+        if let Some(rent_collector) = &ctx.accounts.rent_collector {
+            escrow_state.rent_collector = rent_collector.key();
+        }
+        escrow_state.mint_freezable = ctx.accounts.mint.freeze_authority.is_some();
+        match (
+            &ctx.accounts.recovery_authority,
+            &ctx.accounts.recovery_token_account,
+        ) {
+            (Some(authority), Some(token_account)) => {
+                require!(
+                    token_account.mint == ctx.accounts.mint.key()
+                        && token_account.owner == authority.key(),
+                    EscrowError::InvalidRecoveryAccount
+                );
+                escrow_state.recovery_authority = authority.key();
+                escrow_state.recovery_token_account = token_account.key();
+            }
+            (None, None) => {}
+            _ => return err!(EscrowError::InvalidRecoveryAccount),
+        }
+        escrow_state.funded_amount = amount;
+        escrow_state.min_goal = amount;
+        if let Some(template) = &ctx.accounts.template {
+            escrow_state.apply_template(template)?;
+        }
+        escrow_state.escrow_bump = ctx.bumps.escrow_state;
+        escrow_state.activate(initializer.key)?;
+
+        // Transfer tokens from initializer to the vault.
+        let cpi_accounts = TransferChecked {
+            from: ctx
+                .accounts
+                .initializer_deposit_token_account
+                .to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: initializer.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+        let mint_stats = &mut ctx.accounts.mint_stats;
+        mint_stats.record_opened(ctx.accounts.mint.key(), ctx.bumps.mint_stats);
+        mint_stats.record_deposit(amount);
+
+        emit!(EscrowInitialized {
+            escrow: escrow_state.key(),
+            initializer: *initializer.key,
+            recipient: *recipient.key,
+            arbiter: escrow_state.arbiter,
+            amount,
+            terms_hash,
+            external_id,
+            invoice_hash,
+            withdraw_cosigner: escrow_state.withdraw_cosigner,
+            mint_freezable: escrow_state.mint_freezable,
+            release_not_before: escrow_state.release_not_before,
+            timeout_warning_window: escrow_state.timeout_warning_window,
+            dispute_window: escrow_state.dispute_window,
+            arbiter_registrar: escrow_state.arbiter_registrar,
+            required_qualifications,
+            max_arbiter_fee_bps: escrow_state.max_arbiter_fee_bps,
+            template: escrow_state.template,
+            initializer_handle: handle_of(&ctx.accounts.initializer_profile),
+        });
+
+        Ok(())
+    }
+
+    /// Records the terms of a new escrow without moving any tokens.
+    ///
+    /// The escrow stays in `Funding` until `fund_escrow` calls have deposited
+    /// the full `amount`, giving the counterparty and arbiter a chance to
+    /// review the terms on-chain first. The timeout only starts running once
+    /// the escrow is fully funded.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The context of accounts for the instruction.
+    /// * `amount` - The target amount of tokens to be held in escrow.
+    /// * `timeout` - The duration (in seconds), counted from full funding, after which the escrow can be refunded.
+    /// * `terms_hash` - Hash of the off-chain agreement the escrow was created under.
+    /// * `funding_period` - The duration (in seconds) available to reach `amount`.
+    /// * `crowdfunded` - Whether the escrow is funded by any number of contributors via `contribute` instead of `fund_escrow`.
+    /// * `min_goal` - The minimum deposited amount that still lets the escrow be activated once the funding period ends.
+    /// * `retainage_bps` - Share of the withdrawal (in basis points) held back until final sign-off.
+    /// * `warranty_period` - The duration (in seconds), counted from withdrawal, after which the arbiter may settle the retained share.
+    /// * `external_id` - Caller-defined reference, such as a purchase order number.
+    /// * `invoice_hash` - Hash of the invoice the escrow settles.
+    /// * `release_not_before` - Unix timestamp before which the funds cannot be released
+    ///   to the recipient, or 0 for none.
+    /// * `reject_freezable` - Whether to fail, rather than only set `mint_freezable`,
+    ///   when the mint has a freeze authority.
+    /// * `timeout_warning_window` - Time (in seconds) before the timeout from which
+    ///   `emit_timeout_warning` may be called. Must be positive and at most `timeout`.
+    /// * `dispute_window` - Time (in seconds) the initializer has to review the work
+    ///   after `mark_delivered` before the recipient may withdraw, or 0 for none.
+    ///   Must lie between `MIN_DISPUTE_WINDOW` and `MAX_DISPUTE_WINDOW`.
+    /// * `required_qualifications` - Qualification bits an arbiter must be registered
+    ///   with to `claim_case`, when no arbiter is named. Otherwise 0.
+    /// * `max_arbiter_fee_bps` - Highest fee an arbiter claiming the case may charge,
+    ///   when no arbiter is named. Otherwise 0.
+    ///
+    /// Passing a `template` applies its dispute window, retainage and arbiter fee
+    /// cap; the arguments it replaces must then be 0.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_escrow(
+        ctx: Context<CreateEscrow>,
+        amount: u64,
+        timeout: i64,
+        terms_hash: [u8; 32],
+        funding_period: i64,
+        crowdfunded: bool,
+        min_goal: u64,
+        retainage_bps: u16,
+        warranty_period: i64,
+        external_id: u64,
+        invoice_hash: [u8; 32],
+        release_not_before: i64,
+        reject_freezable: bool,
+        timeout_warning_window: i64,
+        dispute_window: i64,
+        required_qualifications: u32,
+        max_arbiter_fee_bps: u16,
+    ) -> Result<()> {
+        require!(funding_period > 0, EscrowError::InvalidFundingPeriod);
+        require!(release_not_before >= 0, EscrowError::InvalidReleaseDate);
+        require!(
+            !reject_freezable || ctx.accounts.mint.freeze_authority.is_none(),
+            EscrowError::FreezableMint
+        );
+        require!(
+            min_goal > 0 && min_goal <= amount,
+            EscrowError::InvalidFundingGoal
+        );
         require!(
-            initializer.key() != recipient.key(),
-            EscrowError::InvalidRecipient
+            retainage_bps <= MAX_BPS && warranty_period >= 0,
+            EscrowError::InvalidRetainage
         );
+        let initializer = &ctx.accounts.initializer;
+        let recipient = &ctx.accounts.recipient;
 
         let escrow_state = &mut ctx.accounts.escrow_state;
-        escrow_state.initializer = *initializer.key;
-        escrow_state.recipient = *recipient.key;
-        escrow_state.arbiter = *ctx.accounts.arbiter.key;
-        escrow_state.amount = amount;
-        escrow_state.timeout = Clock::get()?
+        let arbiter = ctx.accounts.arbiter.as_ref().map(|arbiter| arbiter.key());
+        escrow_state.record_terms(
+            ctx.accounts.mint.key(),
+            initializer.key(),
+            recipient.key(),
+            arbiter.unwrap_or_default(),
+            amount,
+            timeout,
+            terms_hash,
+            timeout_warning_window,
+            dispute_window,
+        )?;
+        escrow_state.record_listing(
+            arbiter.is_some(),
+            ctx.accounts.arbiter_registrar.as_ref().map(|registrar| registrar.key()),
+            required_qualifications,
+            max_arbiter_fee_bps,
+        )?;
+        match (
+            &ctx.accounts.governance_proposal,
+            &ctx.accounts.governance_program,
+        ) {
+            (Some(proposal), Some(program)) => {
+                governance_proposal_state(proposal, &program.key())?;
+                escrow_state.governance_proposal = proposal.key();
+                escrow_state.governance_program = program.key();
+            }
+            (None, None) => {}
+            _ => return err!(EscrowError::InvalidProposal),
+        }
+        escrow_state.external_id = external_id;
+        escrow_state.invoice_hash = invoice_hash;
+        escrow_state.release_not_before = release_not_before;
+        if let Some(cosigner) = &ctx.accounts.withdraw_cosigner {
+            escrow_state.withdraw_cosigner = cosigner.key();
+        }
+        if let Some(rent_collector) = &ctx.accounts.rent_collector {
+            escrow_state.rent_collector = rent_collector.key();
+        }
+        escrow_state.mint_freezable = ctx.accounts.mint.freeze_authority.is_some();
+        escrow_state.set_status(EscrowStatus::Funding, initializer.key, amount);
+        escrow_state.crowdfunded = crowdfunded;
+        escrow_state.min_goal = min_goal;
+        escrow_state.retainage_bps = retainage_bps;
+        escrow_state.warranty_period = warranty_period;
+        if let Some(template) = &ctx.accounts.template {
+            escrow_state.apply_template(template)?;
+        }
+        escrow_state.funding_deadline = Clock::get()?
             .unix_timestamp
-            .checked_add(timeout)
+            .checked_add(funding_period)
             .ok_or(EscrowError::Overflow)?;
-        escrow_state.status = EscrowStatus::Initialized;
-        escrow_state.vault_bump = ctx.bumps.vault;
         escrow_state.escrow_bump = ctx.bumps.escrow_state;
+        ctx.accounts
+            .mint_stats
+            .record_opened(ctx.accounts.mint.key(), ctx.bumps.mint_stats);
+
+        emit!(EscrowCreated {
+            escrow: escrow_state.key(),
+            initializer: *initializer.key,
+            recipient: *recipient.key,
+            arbiter: escrow_state.arbiter,
+            amount,
+            terms_hash,
+            funding_deadline: escrow_state.funding_deadline,
+            crowdfunded,
+            min_goal,
+            governance_proposal: escrow_state.governance_proposal,
+            retainage_bps: escrow_state.retainage_bps,
+            external_id,
+            invoice_hash,
+            withdraw_cosigner: escrow_state.withdraw_cosigner,
+            mint_freezable: escrow_state.mint_freezable,
+            release_not_before: escrow_state.release_not_before,
+            timeout_warning_window: escrow_state.timeout_warning_window,
+            dispute_window: escrow_state.dispute_window,
+            arbiter_registrar: escrow_state.arbiter_registrar,
+            required_qualifications,
+            max_arbiter_fee_bps: escrow_state.max_arbiter_fee_bps,
+            template: escrow_state.template,
+            initializer_handle: handle_of(&ctx.accounts.initializer_profile),
+        });
+
+        Ok(())
+    }
+
+    /// Deposits part or all of the remaining amount into the vault of an
+    /// escrow created with `create_escrow`. The deposit that reaches the
+    /// target amount activates the escrow and starts its timeout.
+    pub fn fund_escrow(ctx: Context<FundEscrow>, amount: u64) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let initializer = &ctx.accounts.initializer;
+
+        require!(!escrow_state.crowdfunded, EscrowError::ContributionsOnly);
+        let funded_amount = escrow_state.record_deposit(amount, initializer.key)?;
 
         // Transfer tokens from initializer to the vault.
-        let cpi_accounts = Transfer {
+        let cpi_accounts = TransferChecked {
             from: ctx
                 .accounts
                 .initializer_deposit_token_account
                 .to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
             to: ctx.accounts.vault.to_account_info(),
             authority: initializer.to_account_info(),
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::transfer(cpi_ctx, amount)?;
+        token::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+        ctx.accounts.mint_stats.record_deposit(amount);
 
-        emit!(EscrowInitialized {
+        emit!(EscrowFunded {
             escrow: escrow_state.key(),
             initializer: *initializer.key,
-            recipient: *recipient.key,
-            arbiter: *ctx.accounts.arbiter.key,
             amount,
+            funded_amount,
+            timeout: escrow_state.timeout,
         });
 
         Ok(())
     }
 
-    /// Allows the recipient to withdraw tokens from the escrow.
-    pub fn withdraw(ctx: Context<Withdraw>) -> Result<()> {
+    /// Deposits `amount` more tokens into an active escrow and raises its
+    /// amount accordingly, so the terms can grow without cancelling and
+    /// recreating the escrow. Crowdfunded escrows only take contributions,
+    /// and trade escrows keep the deposit their price was set for.
+    pub fn top_up(ctx: Context<TopUp>, amount: u64) -> Result<()> {
         let escrow_state = &mut ctx.accounts.escrow_state;
-        let recipient = &ctx.accounts.recipient;
+        let initializer = &ctx.accounts.initializer;
 
+        require!(amount > 0, EscrowError::InvalidAmount);
         require!(
             escrow_state.status == EscrowStatus::Initialized,
             EscrowError::InvalidState
         );
+        require!(!escrow_state.crowdfunded, EscrowError::ContributionsOnly);
+        // The deposit of a trade is fixed by its price.
+        require!(!escrow_state.trade_mode, EscrowError::InvalidTrade);
         require!(
             Clock::get()?.unix_timestamp < escrow_state.timeout,
             EscrowError::TimeoutExpired
         );
+        escrow_state.amount = escrow_state
+            .amount
+            .checked_add(amount)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.funded_amount = escrow_state.amount;
 
-        // Transfer tokens from the vault to the recipient.
-        let escrow_key = escrow_state.key();
-        let signer_seeds: &[&[&[u8]]] = &[&[
-            b"vault".as_ref(),
-            escrow_key.as_ref(),
-            &[escrow_state.vault_bump],
-        ]];
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.vault.to_account_info(),
-            to: ctx
+        // Transfer tokens from initializer to the vault.
+        let cpi_accounts = TransferChecked {
+            from: ctx
                 .accounts
-                .recipient_deposit_token_account
+                .initializer_deposit_token_account
                 .to_account_info(),
-            authority: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: initializer.to_account_info(),
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx =
-            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-        token::transfer(cpi_ctx, escrow_state.amount)?;
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+        ctx.accounts.mint_stats.record_deposit(amount);
 
-        escrow_state.status = EscrowStatus::Withdrawn;
+        emit!(EscrowToppedUp {
+            escrow: escrow_state.key(),
+            initializer: *initializer.key,
+            amount,
+            total_amount: escrow_state.amount,
+            external_id: escrow_state.external_id,
+        });
 
-        emit!(EscrowWithdrawn {
+        Ok(())
+    }
+
+    /// Deposits tokens into a crowdfunded escrow on behalf of any contributor,
+    /// recording the running total in the contributor's `Contribution`
+    /// account. The deposit that reaches the target amount activates the
+    /// escrow.
+    pub fn contribute(ctx: Context<Contribute>, amount: u64) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let contributor = &ctx.accounts.contributor;
+
+        require!(
+            escrow_state.crowdfunded,
+            EscrowError::ContributionsNotEnabled
+        );
+        let funded_amount = escrow_state.record_deposit(amount, contributor.key)?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let contribution = &mut ctx.accounts.contribution;
+        if contribution.first_contributed_at == 0 {
+            contribution.escrow = escrow_state.key();
+            contribution.contributor = *contributor.key;
+            contribution.first_contributed_at = now;
+            contribution.bump = ctx.bumps.contribution;
+        }
+        contribution.amount = contribution
+            .amount
+            .checked_add(amount)
+            .ok_or(EscrowError::Overflow)?;
+        contribution.last_contributed_at = now;
+
+        // Transfer tokens from the contributor to the vault.
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.contributor_token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: contributor.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+        ctx.accounts.mint_stats.record_deposit(amount);
+
+        emit!(EscrowContributed {
             escrow: escrow_state.key(),
-            recipient: *recipient.key,
-            amount: escrow_state.amount,
+            contributor: *contributor.key,
+            contribution: contribution.key(),
+            amount,
+            total_contributed: contribution.amount,
+            funded_amount,
+            timeout: escrow_state.timeout,
         });
 
         Ok(())
     }
 
-    /// Allows the initializer to get a refund after the timeout has expired.
-    pub fn refund(ctx: Context<Refund>) -> Result<()> {
+    /// Activates an escrow whose funding period ended with at least
+    /// `min_goal` deposited. The escrow amount becomes whatever was
+    /// deposited and the timeout starts now. Callable by anyone.
+    ///
+    /// The deposit must still cover every milestone added, and an escrow with
+    /// an open trade is not activated short, since its price was set for the
+    /// full amount.
+    pub fn activate_escrow(ctx: Context<ActivateEscrow>) -> Result<()> {
         let escrow_state = &mut ctx.accounts.escrow_state;
-        let initializer = &ctx.accounts.initializer;
 
         require!(
-            escrow_state.status == EscrowStatus::Initialized,
+            escrow_state.status == EscrowStatus::Funding,
             EscrowError::InvalidState
         );
         require!(
-            Clock::get()?.unix_timestamp >= escrow_state.timeout,
-            EscrowError::RefundNotAllowed
+            Clock::get()?.unix_timestamp >= escrow_state.funding_deadline,
+            EscrowError::FundingPeriodActive
+        );
+        require!(
+            escrow_state.funded_amount >= escrow_state.min_goal,
+            EscrowError::FundingGoalNotReached
+        );
+        require!(
+            escrow_state.funded_amount >= escrow_state.milestone_total
+                && !escrow_state.trade_mode,
+            EscrowError::ActivationBlocked
         );
 
-        // Transfer tokens from the vault back to the initializer.
-        let escrow_key = escrow_state.key();
+        escrow_state.amount = escrow_state.funded_amount;
+        // Activation is permissionless, so there is no acting party to trace.
+        escrow_state.activate(&Pubkey::default())?;
+
+        emit!(EscrowActivated {
+            escrow: escrow_state.key(),
+            amount: escrow_state.amount,
+            timeout: escrow_state.timeout,
+        });
+
+        Ok(())
+    }
+
+    /// Returns a contributor's full contribution once a crowdfunded escrow
+    /// can no longer pay out to the recipient: it was cancelled or refunded,
+    /// missed its funding goal, or timed out. The contribution account is kept
+    /// as a receipt and marked as claimed.
+    pub fn claim_contribution(ctx: Context<ClaimContribution>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let contribution = &mut ctx.accounts.contribution;
+
+        require!(!contribution.claimed, EscrowError::AlreadyClaimed);
+
+        let now = Clock::get()?.unix_timestamp;
+        let claimable = match escrow_state.status {
+            EscrowStatus::Refunded | EscrowStatus::Cancelled => true,
+            EscrowStatus::Funding => escrow_state.missed_funding_goal(now),
+            EscrowStatus::Initialized => now >= escrow_state.timeout,
+            _ => false,
+        };
+        require!(claimable, EscrowError::ClaimNotAllowed);
+
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+
+        // Transfer the contribution from the vault back to the contributor.
+        let address_seed = escrow_state.address_seed();
         let signer_seeds: &[&[&[u8]]] = &[&[
-            b"vault".as_ref(),
-            escrow_key.as_ref(),
-            &[escrow_state.vault_bump],
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
         ]];
-        let cpi_accounts = Transfer {
+        let cpi_accounts = TransferChecked {
             from: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
             to: ctx
                 .accounts
-                .initializer_refund_token_account
+                .contributor_refund_token_account
                 .to_account_info(),
-            authority: ctx.accounts.vault.to_account_info(),
+            authority: escrow_state.to_account_info(),
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx =
             CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-        token::transfer(cpi_ctx, escrow_state.amount)?;
+        token::transfer_checked(cpi_ctx, contribution.amount, ctx.accounts.mint.decimals)?;
+        ctx.accounts.mint_stats.record_release(contribution.amount);
 
-        escrow_state.status = EscrowStatus::Refunded;
+        contribution.claimed = true;
+        escrow_state.funded_amount = escrow_state
+            .funded_amount
+            .checked_sub(contribution.amount)
+            .ok_or(EscrowError::Overflow)?;
+        // The first claim settles the escrow so it can no longer be released.
+        if matches!(
+            escrow_state.status,
+            EscrowStatus::Funding | EscrowStatus::Initialized
+        ) {
+            escrow_state.set_status(
+                EscrowStatus::Refunded,
+                &contribution.contributor,
+                contribution.amount,
+            );
+            ctx.accounts.mint_stats.record_settled();
+        }
 
-        emit!(EscrowRefunded {
+        emit!(ContributionClaimed {
             escrow: escrow_state.key(),
-            initializer: *initializer.key,
-            amount: escrow_state.amount,
+            contributor: contribution.contributor,
+            contribution: contribution.key(),
+            amount: contribution.amount,
         });
 
         Ok(())
     }
 
-    /// Allows the initializer to cancel the escrow before timeout.
-    pub fn cancel(ctx: Context<Cancel>) -> Result<()> {
+    /// Allows the recipient to withdraw tokens from the escrow.
+    ///
+    /// Escrows created with a governance proposal additionally require that
+    /// proposal to have succeeded; otherwise the initializer can refund once
+    /// the timeout, acting as the voting deadline, has passed. Escrows created
+    /// with a retainage keep `retainage_bps` of the amount in the vault until
+    /// `release_retainage` or `resolve_retainage`. Escrows with a
+    /// `release_not_before` date cannot be withdrawn before it, and escrows
+    /// with a `dispute_window` not before the initializer had that long to
+    /// review the work marked delivered.
+    pub fn withdraw(ctx: Context<Withdraw>) -> Result<()> {
         let escrow_state = &mut ctx.accounts.escrow_state;
-        let initializer = &ctx.accounts.initializer;
+        let recipient = &ctx.accounts.recipient;
 
         require!(
             escrow_state.status == EscrowStatus::Initialized,
             EscrowError::InvalidState
         );
+        require!(!escrow_state.trade_mode, EscrowError::TradeRequiresExchange);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < escrow_state.timeout, EscrowError::TimeoutExpired);
         require!(
-            Clock::get()?.unix_timestamp < escrow_state.timeout,
-            EscrowError::CancelNotAllowed
+            now >= escrow_state.release_not_before,
+            EscrowError::ReleaseNotYetAllowed
+        );
+        escrow_state.check_review_over(now)?;
+        if escrow_state.governance_proposal != Pubkey::default() {
+            let proposal = ctx
+                .accounts
+                .governance_proposal
+                .as_ref()
+                .ok_or(EscrowError::InvalidProposal)?;
+            require_keys_eq!(
+                proposal.key(),
+                escrow_state.governance_proposal,
+                EscrowError::InvalidProposal
+            );
+            require!(
+                matches!(
+                    governance_proposal_state(proposal, &escrow_state.governance_program)?,
+                    PROPOSAL_STATE_SUCCEEDED | PROPOSAL_STATE_EXECUTING | PROPOSAL_STATE_COMPLETED
+                ),
+                EscrowError::ProposalNotApproved
+            );
+        }
+        if escrow_state.withdraw_cosigner != Pubkey::default() {
+            let cosigner = ctx
+                .accounts
+                .withdraw_cosigner
+                .as_ref()
+                .ok_or(EscrowError::MissingCosigner)?;
+            require_keys_eq!(
+                cosigner.key(),
+                escrow_state.withdraw_cosigner,
+                EscrowError::MissingCosigner
+            );
+        }
+        require!(
+            escrow_state.disputed_milestones == 0,
+            EscrowError::MilestoneDisputeOpen
         );
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
 
-        // Transfer tokens from the vault back to the initializer.
-        let escrow_key = escrow_state.key();
+        // Transfer tokens from the vault to the recipient.
+        let address_seed = escrow_state.address_seed();
         let signer_seeds: &[&[&[u8]]] = &[&[
-            b"vault".as_ref(),
-            escrow_key.as_ref(),
-            &[escrow_state.vault_bump],
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
         ]];
-        let cpi_accounts = Transfer {
+        let cpi_accounts = TransferChecked {
             from: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
             to: ctx
                 .accounts
-                .initializer_refund_token_account
+                .recipient_deposit_token_account
                 .to_account_info(),
-            authority: ctx.accounts.vault.to_account_info(),
+            authority: escrow_state.to_account_info(),
         };
+        let retained_amount = escrow_state.retainage(escrow_state.amount)?;
+        let payout = escrow_state
+            .amount
+            .checked_sub(retained_amount)
+            .ok_or(EscrowError::Overflow)?;
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx =
             CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-        token::transfer(cpi_ctx, escrow_state.amount)?;
+        token::transfer_checked(cpi_ctx, payout, ctx.accounts.mint.decimals)?;
 
-        escrow_state.status = EscrowStatus::Cancelled;
+        escrow_state.set_status(EscrowStatus::Withdrawn, recipient.key, payout);
+        ctx.accounts.mint_stats.record_release(payout);
+        ctx.accounts.mint_stats.record_settled();
+        escrow_state.retained_amount = escrow_state
+            .retained_amount
+            .checked_add(retained_amount)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.warranty_ends_at = now
+            .checked_add(escrow_state.warranty_period)
+            .ok_or(EscrowError::Overflow)?;
 
-        emit!(EscrowCancelled {
+        emit!(EscrowWithdrawn {
             escrow: escrow_state.key(),
-            initializer: *initializer.key,
+            recipient: *recipient.key,
+            amount: payout,
+            retained_amount,
+            external_id: escrow_state.external_id,
+            auditor: escrow_state.auditor,
+            template: escrow_state.template,
         });
 
         Ok(())
     }
 
-    /// Allows the arbiter to resolve the dispute and release funds.
-    pub fn resolve_by_arbiter(ctx: Context<ResolveByArbiter>, release_to_recipient: bool) -> Result<()> {
+    /// Lets the recipient of an escrow with a `dispute_window` mark the work
+    /// delivered, opening the window in which the initializer may review it
+    /// and `raise_dispute`. The recipient can withdraw once it has passed.
+    /// The window has to end before the timeout, so the initializer is never
+    /// left without a refund and a chance to review at the same time.
+    pub fn mark_delivered(ctx: Context<MarkDelivered>) -> Result<()> {
         let escrow_state = &mut ctx.accounts.escrow_state;
 
         require!(
             escrow_state.status == EscrowStatus::Initialized,
             EscrowError::InvalidState
         );
+        require!(
+            escrow_state.dispute_window > 0,
+            EscrowError::NoDisputeWindow
+        );
+        require!(escrow_state.delivered_at == 0, EscrowError::AlreadyDelivered);
+        let now = Clock::get()?.unix_timestamp;
+        let review_ends_at = now
+            .checked_add(escrow_state.dispute_window)
+            .ok_or(EscrowError::Overflow)?;
+        require!(
+            review_ends_at < escrow_state.timeout,
+            EscrowError::TimeoutExpired
+        );
 
-        let escrow_key = escrow_state.key();
-        let signer_seeds: &[&[&[u8]]] = &[&[
-            b"vault".as_ref(),
-            escrow_key.as_ref(),
-            &[escrow_state.vault_bump],
-        ]];
+        escrow_state.delivered_at = now;
 
-        if release_to_recipient {
-            // Transfer to recipient
-            let cpi_accounts = Transfer {
-                from: ctx.accounts.vault.to_account_info(),
-                to: ctx.accounts.recipient_deposit_token_account.to_account_info(),
-                authority: ctx.accounts.vault.to_account_info(),
-            };
-            let cpi_program = ctx.accounts.token_program.to_account_info();
-            let cpi_ctx =
-                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-            token::transfer(cpi_ctx, escrow_state.amount)?;
-            escrow_state.status = EscrowStatus::Withdrawn;
-        } else {
-            // Refund to initializer
-            let cpi_accounts = Transfer {
-                from: ctx.accounts.vault.to_account_info(),
-                to: ctx.accounts.initializer_refund_token_account.to_account_info(),
-                authority: ctx.accounts.vault.to_account_info(),
-            };
-            let cpi_program = ctx.accounts.token_program.to_account_info();
-            let cpi_ctx =
-                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-            token::transfer(cpi_ctx, escrow_state.amount)?;
-            escrow_state.status = EscrowStatus::Refunded;
-        }
+        emit!(EscrowDelivered {
+            escrow: escrow_state.key(),
+            recipient: *ctx.accounts.recipient.key,
+            review_ends_at,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the recipient register a beneficiary token account that may claim
+    /// the escrow in their place if they have not withdrawn `window` seconds
+    /// after it became claimable.
+    pub fn register_beneficiary(ctx: Context<RegisterBeneficiary>, window: i64) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Funding | EscrowStatus::Initialized
+            ),
+            EscrowError::InvalidState
+        );
+        require!(window > 0, EscrowError::InvalidBeneficiary);
+        require!(
+            ctx.accounts.beneficiary_token_account.mint == escrow_state.mint,
+            EscrowError::InvalidBeneficiary
+        );
+        // Withdrawals gated on a proposal or co-signer cannot be bypassed.
+        require!(
+            escrow_state.governance_proposal == Pubkey::default()
+                && escrow_state.withdraw_cosigner == Pubkey::default(),
+            EscrowError::InvalidBeneficiary
+        );
+
+        escrow_state.beneficiary_token_account = ctx.accounts.beneficiary_token_account.key();
+        escrow_state.beneficiary_window = window;
 
-        emit!(EscrowResolved {
+        emit!(BeneficiaryRegistered {
             escrow: escrow_state.key(),
-            arbiter: *ctx.accounts.arbiter.key,
-            release_to_recipient,
+            recipient: *ctx.accounts.recipient.key,
+            beneficiary: ctx.accounts.beneficiary_token_account.owner,
+            beneficiary_token_account: escrow_state.beneficiary_token_account,
+            window,
         });
 
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct Cancel<'info> {
-    #[account(mut)]
-    pub initializer: Signer<'info>,
-    #[account(mut)]
-    pub initializer_refund_token_account: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
-        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.recipient.as_ref()],
-        bump = escrow_state.escrow_bump,
-    )]
-    pub escrow_state: Account<'info, Escrow>,
-    #[account(
-        mut,
-        seeds = [b"vault", escrow_state.key().as_ref()],
-        bump = escrow_state.vault_bump,
-    )]
-    pub vault: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
-}
+    /// Lets the owner of the registered beneficiary token account withdraw on
+    /// the recipient's behalf once the recipient stayed inactive for the
+    /// registered window. The timeout, retainage and review window still apply.
+    pub fn claim_as_beneficiary(ctx: Context<ClaimAsBeneficiary>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            escrow_state.status == EscrowStatus::Initialized,
+            EscrowError::InvalidState
+        );
+        require!(!escrow_state.trade_mode, EscrowError::TradeRequiresExchange);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < escrow_state.timeout, EscrowError::TimeoutExpired);
+        require!(
+            now >= escrow_state.release_not_before,
+            EscrowError::ReleaseNotYetAllowed
+        );
+        escrow_state.check_review_over(now)?;
+        let claimable_since = escrow_state
+            .timeout
+            .checked_sub(escrow_state.duration)
+            .ok_or(EscrowError::Overflow)?;
+        let beneficiary_claimable_at = claimable_since
+            .checked_add(escrow_state.beneficiary_window)
+            .ok_or(EscrowError::Overflow)?;
+        require!(
+            now >= beneficiary_claimable_at,
+            EscrowError::RecipientStillActive
+        );
+        require!(
+            escrow_state.disputed_milestones == 0,
+            EscrowError::MilestoneDisputeOpen
+        );
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+
+        let address_seed = escrow_state.address_seed();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
+        ]];
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.beneficiary_token_account.to_account_info(),
+            authority: escrow_state.to_account_info(),
+        };
+        let retained_amount = escrow_state.retainage(escrow_state.amount)?;
+        let payout = escrow_state
+            .amount
+            .checked_sub(retained_amount)
+            .ok_or(EscrowError::Overflow)?;
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx =
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer_checked(cpi_ctx, payout, ctx.accounts.mint.decimals)?;
+
+        escrow_state.set_status(EscrowStatus::Withdrawn, ctx.accounts.beneficiary.key, payout);
+        ctx.accounts.mint_stats.record_release(payout);
+        ctx.accounts.mint_stats.record_settled();
+        escrow_state.retained_amount = escrow_state
+            .retained_amount
+            .checked_add(retained_amount)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.warranty_ends_at = now
+            .checked_add(escrow_state.warranty_period)
+            .ok_or(EscrowError::Overflow)?;
+
+        emit!(BeneficiaryClaimed {
+            escrow: escrow_state.key(),
+            beneficiary: *ctx.accounts.beneficiary.key,
+            amount: payout,
+            retained_amount,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the initializer and recipient jointly switch the escrow to
+    /// heartbeat mode, where either of them can push the timeout out to
+    /// `increment` seconds from now with `heartbeat`, as long as the escrow
+    /// stays claimable for at most `max_duration` seconds in total.
+    pub fn enable_heartbeat(
+        ctx: Context<EnableHeartbeat>,
+        increment: i64,
+        max_duration: i64,
+    ) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Funding | EscrowStatus::Initialized
+            ),
+            EscrowError::InvalidState
+        );
+        require!(
+            increment > 0 && max_duration >= escrow_state.duration,
+            EscrowError::InvalidHeartbeat
+        );
+
+        escrow_state.heartbeat_increment = increment;
+        escrow_state.heartbeat_max_duration = max_duration;
+
+        emit!(HeartbeatEnabled {
+            escrow: escrow_state.key(),
+            increment,
+            max_duration,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the initializer and recipient jointly require that every
+    /// instruction moving tokens out of the vault runs in a transaction whose
+    /// top-level instructions invoke no program outside
+    /// `SETTLEMENT_GUARD_ALLOWLIST`, so a release cannot be sandwiched between
+    /// instructions that manipulate the conditions it depends on. Programs
+    /// reached through CPI are not visible to the check. The guard cannot be
+    /// turned off again.
+    pub fn enable_settlement_guard(ctx: Context<EnableSettlementGuard>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Funding
+                    | EscrowStatus::Initialized
+                    | EscrowStatus::Disputed
+            ),
+            EscrowError::InvalidState
+        );
+
+        escrow_state.settlement_guard = true;
+
+        emit!(SettlementGuardEnabled {
+            escrow: escrow_state.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Pushes the timeout of a heartbeat-mode escrow out to
+    /// `heartbeat_increment` seconds from now, capped at
+    /// `heartbeat_max_duration` after activation.
+    pub fn heartbeat(ctx: Context<Heartbeat>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            escrow_state.status == EscrowStatus::Initialized,
+            EscrowError::InvalidState
+        );
+        require!(
+            escrow_state.heartbeat_increment > 0,
+            EscrowError::InvalidHeartbeat
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < escrow_state.timeout, EscrowError::TimeoutExpired);
+
+        // `timeout - duration` is the activation time; extending both keeps it.
+        let activated_at = escrow_state
+            .timeout
+            .checked_sub(escrow_state.duration)
+            .ok_or(EscrowError::Overflow)?;
+        let max_timeout = activated_at
+            .checked_add(escrow_state.heartbeat_max_duration)
+            .ok_or(EscrowError::Overflow)?;
+        let new_timeout = now
+            .checked_add(escrow_state.heartbeat_increment)
+            .ok_or(EscrowError::Overflow)?
+            .min(max_timeout);
+        require!(
+            new_timeout > escrow_state.timeout,
+            EscrowError::HeartbeatLimitReached
+        );
+
+        escrow_state.timeout = new_timeout;
+        escrow_state.duration = new_timeout - activated_at;
+        escrow_state.timeout_warning_emitted = false;
+
+        emit!(EscrowExtended {
+            escrow: escrow_state.key(),
+            party: *ctx.accounts.party.key,
+            timeout: new_timeout,
+        });
+
+        Ok(())
+    }
+
+    /// Emits `EscrowExpiringSoon` once an escrow is within its
+    /// `timeout_warning_window` of its timeout. Anyone may call it, but only
+    /// the first call succeeds, so notifiers can rely on it as a trigger.
+    pub fn emit_timeout_warning(ctx: Context<EmitTimeoutWarning>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            escrow_state.status == EscrowStatus::Initialized,
+            EscrowError::InvalidState
+        );
+        require!(
+            !escrow_state.timeout_warning_emitted,
+            EscrowError::TimeoutWarningEmitted
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < escrow_state.timeout, EscrowError::TimeoutExpired);
+        require!(
+            now >= escrow_state.timeout.saturating_sub(escrow_state.warning_window()),
+            EscrowError::TimeoutNotNear
+        );
+
+        escrow_state.timeout_warning_emitted = true;
+
+        emit!(EscrowExpiringSoon {
+            escrow: escrow_state.key(),
+            timeout: escrow_state.timeout,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Compares the vault balance with what the escrow's state says it should
+    /// hold and emits `VaultDiscrepancy` if they differ. Anyone may call it.
+    ///
+    /// The program only ever moves the amounts it records, so a surplus is
+    /// tokens sent straight to the vault address. It belongs to the
+    /// initializer, who funded the escrow, and `sweep_surplus` returns it.
+    pub fn reconcile_vault(ctx: Context<ReconcileVault>) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+        let expected = escrow_state.expected_vault_balance();
+        let actual = ctx.accounts.vault.amount;
+
+        if actual != expected {
+            emit!(VaultDiscrepancy {
+                escrow: escrow_state.key(),
+                expected,
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Lets the escrow's auditor record the escrow's full state on-chain: it
+    /// emits `AuditSnapshot` with the current terms, the balances the escrow
+    /// has booked, and the actual vault balance.
+    pub fn request_audit_snapshot(ctx: Context<RequestAuditSnapshot>) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+
+        emit!(AuditSnapshot {
+            escrow: escrow_state.key(),
+            auditor: escrow_state.auditor,
+            status: escrow_state.status.clone(),
+            mint: escrow_state.mint,
+            initializer: escrow_state.initializer,
+            recipient: escrow_state.recipient,
+            arbiter: escrow_state.arbiter,
+            amount: escrow_state.amount,
+            funded_amount: escrow_state.funded_amount,
+            retained_amount: escrow_state.retained_amount,
+            expected_vault_balance: escrow_state.expected_vault_balance(),
+            vault_balance: ctx.accounts.vault.amount,
+            timeout: escrow_state.timeout,
+            external_id: escrow_state.external_id,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Returns any vault balance above `expected_vault_balance`, i.e. tokens
+    /// sent straight to the vault address, to the initializer. Anyone may
+    /// call it, as the surplus can only go to the initializer's account.
+    pub fn sweep_surplus(ctx: Context<SweepSurplus>) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+        let surplus = ctx
+            .accounts
+            .vault
+            .amount
+            .saturating_sub(escrow_state.expected_vault_balance());
+        require!(surplus > 0, EscrowError::NoSurplus);
+
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+
+        let address_seed = escrow_state.address_seed();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
+        ]];
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx
+                .accounts
+                .initializer_refund_token_account
+                .to_account_info(),
+            authority: escrow_state.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx =
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer_checked(cpi_ctx, surplus, ctx.accounts.mint.decimals)?;
+
+        emit!(SurplusSwept {
+            escrow: escrow_state.key(),
+            amount: surplus,
+        });
+
+        Ok(())
+    }
+
+    /// Records a message from the initializer, recipient or arbiter to
+    /// another of them as a `MessagePosted` event. Only the hash of the
+    /// off-chain content goes on-chain, timestamped and tied to the escrow.
+    pub fn post_message(
+        ctx: Context<PostMessage>,
+        content_hash: [u8; 32],
+        recipient_role: EscrowRole,
+    ) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+        let sender = ctx.accounts.sender.key;
+
+        require!(content_hash != [0; 32], EscrowError::EmptyMessage);
+        let sender_role = escrow_state
+            .role_of(sender)
+            .ok_or(EscrowError::NotEscrowParty)?;
+
+        emit!(MessagePosted {
+            escrow: escrow_state.key(),
+            sender: *sender,
+            sender_role,
+            recipient_role,
+            content_hash,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Registers or replaces the signer's display handle, such as
+    /// `acme-marketplace`, in their `PartyProfile`. Handles are 1 to
+    /// `MAX_HANDLE_LEN` bytes of ASCII letters, digits, `-`, `_` and `.`.
+    /// Events of escrows the party acts on then carry the handle, when the
+    /// profile is passed along, so explorers can show it instead of the key.
+    pub fn set_handle(ctx: Context<SetHandle>, handle: String) -> Result<()> {
+        require!(
+            !handle.is_empty()
+                && handle.len() <= MAX_HANDLE_LEN
+                && handle
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.')),
+            EscrowError::InvalidHandle
+        );
+
+        let profile = &mut ctx.accounts.profile;
+        profile.owner = ctx.accounts.owner.key();
+        profile.handle = handle;
+        profile.bump = ctx.bumps.profile;
+
+        emit!(HandleSet {
+            owner: profile.owner,
+            handle: profile.handle.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Removes the signer's handle, closing their `PartyProfile` and
+    /// returning its rent.
+    pub fn clear_handle(ctx: Context<ClearHandle>) -> Result<()> {
+        emit!(HandleCleared {
+            owner: ctx.accounts.owner.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Lets the initializer split the escrow into milestones that can be
+    /// released one at a time with `release_milestone`. Milestones are added
+    /// in order, starting at index 0; `deadline` is the date the milestone is
+    /// due, recorded for the parties' reference. Disputes over the milestone
+    /// go to the escrow's arbiter, unless a dedicated `arbiter` co-signs to
+    /// take on this milestone alone.
+    pub fn add_milestone(
+        ctx: Context<AddMilestone>,
+        index: u8,
+        amount: u64,
+        deadline: i64,
+    ) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Funding | EscrowStatus::Initialized
+            ),
+            EscrowError::InvalidState
+        );
+        // Contributors are refunded in full and trades settle in one
+        // exchange, so neither can pay out in parts.
+        let milestone_total = escrow_state
+            .milestone_total
+            .checked_add(amount)
+            .ok_or(EscrowError::Overflow)?;
+        require!(
+            !escrow_state.crowdfunded
+                && !escrow_state.trade_mode
+                && index == escrow_state.milestone_count
+                && amount > 0
+                && milestone_total <= escrow_state.amount,
+            EscrowError::InvalidMilestone
+        );
+        escrow_state.milestone_count = escrow_state
+            .milestone_count
+            .checked_add(1)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.milestone_total = milestone_total;
+
+        let milestone = &mut ctx.accounts.milestone;
+        milestone.escrow = escrow_state.key();
+        milestone.index = index;
+        milestone.amount = amount;
+        milestone.deadline = deadline;
+        milestone.bump = ctx.bumps.milestone;
+        milestone.arbiter = ctx
+            .accounts
+            .arbiter
+            .as_ref()
+            .map_or(Pubkey::default(), |arbiter| arbiter.key());
+
+        emit!(MilestoneAdded {
+            escrow: escrow_state.key(),
+            index,
+            amount,
+            deadline,
+            arbiter: milestone.arbiter,
+        });
+
+        Ok(())
+    }
+
+    /// Pays a milestone out to the recipient and deducts it from the escrow
+    /// amount, so later withdrawals and refunds only cover the rest. Callable
+    /// by the initializer while the escrow is active, or by the arbiter once
+    /// they have accepted the role and the escrow is in dispute.
+    /// Like `withdraw`, it waits for the release date, honours the settlement
+    /// guard and holds back the retainage share of the milestone until final
+    /// sign-off. Releasing the last of the amount marks the escrow `Withdrawn`.
+    pub fn release_milestone(ctx: Context<ReleaseMilestone>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let authority = ctx.accounts.authority.key;
+
+        let required_status = if *authority == escrow_state.initializer {
+            EscrowStatus::Initialized
+        } else if *authority == escrow_state.arbiter && escrow_state.arbiter_accepted {
+            EscrowStatus::Disputed
+        } else {
+            return err!(EscrowError::InvalidParty);
+        };
+        require!(
+            escrow_state.status == required_status,
+            EscrowError::InvalidState
+        );
+        require!(
+            !ctx.accounts.milestone.released,
+            EscrowError::MilestoneReleased
+        );
+        require!(
+            !ctx.accounts.milestone.disputed,
+            EscrowError::MilestoneDisputeOpen
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now >= escrow_state.release_not_before,
+            EscrowError::ReleaseNotYetAllowed
+        );
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+        let amount = ctx.accounts.milestone.amount;
+        let remaining = escrow_state
+            .amount
+            .checked_sub(amount)
+            .ok_or(EscrowError::InvalidMilestone)?;
+        let retained_amount = escrow_state.retainage(amount)?;
+        let payout = amount
+            .checked_sub(retained_amount)
+            .ok_or(EscrowError::Overflow)?;
+
+        let address_seed = escrow_state.address_seed();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
+        ]];
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx
+                .accounts
+                .recipient_deposit_token_account
+                .to_account_info(),
+            authority: escrow_state.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx =
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer_checked(cpi_ctx, payout, ctx.accounts.mint.decimals)?;
+
+        ctx.accounts.milestone.released = true;
+        escrow_state.amount = remaining;
+        escrow_state.funded_amount = remaining;
+        escrow_state.milestone_total = escrow_state
+            .milestone_total
+            .checked_sub(amount)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.released_amount = escrow_state
+            .released_amount
+            .checked_add(amount)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.retained_amount = escrow_state
+            .retained_amount
+            .checked_add(retained_amount)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.warranty_ends_at = now
+            .checked_add(escrow_state.warranty_period)
+            .ok_or(EscrowError::Overflow)?;
+        ctx.accounts.mint_stats.record_release(payout);
+        if remaining == 0 {
+            escrow_state.set_status(EscrowStatus::Withdrawn, authority, payout);
+            ctx.accounts.mint_stats.record_settled();
+        }
+
+        emit!(MilestoneReleased {
+            escrow: escrow_state.key(),
+            index: ctx.accounts.milestone.index,
+            amount: payout,
+            retained_amount,
+            released_by: *authority,
+            remaining,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the initializer or the recipient dispute a single milestone. Only
+    /// that milestone is frozen: other milestones can still be released, but
+    /// the escrow as a whole cannot be withdrawn, refunded, cancelled or put
+    /// in dispute until the milestone's arbiter has ruled on it.
+    pub fn raise_milestone_dispute(
+        ctx: Context<RaiseMilestoneDispute>,
+        reason_code: u8,
+    ) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let milestone = &mut ctx.accounts.milestone;
+        let party = ctx.accounts.party.key;
+
+        require!(
+            escrow_state.status == EscrowStatus::Initialized,
+            EscrowError::InvalidState
+        );
+        require!(!milestone.released, EscrowError::MilestoneReleased);
+        require!(!milestone.disputed, EscrowError::MilestoneDisputeOpen);
+        // A dedicated arbiter accepted by co-signing `add_milestone`.
+        if milestone.arbiter == Pubkey::default() {
+            require!(
+                escrow_state.arbiter_accepted,
+                EscrowError::ArbiterNotAccepted
+            );
+        }
+        require!(
+            Clock::get()?.unix_timestamp < escrow_state.timeout,
+            EscrowError::TimeoutExpired
+        );
+
+        milestone.disputed = true;
+        escrow_state.disputed_milestones = escrow_state
+            .disputed_milestones
+            .checked_add(1)
+            .ok_or(EscrowError::Overflow)?;
+
+        emit!(MilestoneDisputed {
+            escrow: escrow_state.key(),
+            index: milestone.index,
+            raised_by: *party,
+            arbiter: milestone.arbiter_for(escrow_state),
+            reason_code,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the milestone's arbiter settle a disputed milestone, either paying
+    /// it to the recipient like `release_milestone` (retainage included) or
+    /// returning it to the initializer. Either way the milestone counts as
+    /// released and its amount leaves the escrow.
+    pub fn resolve_milestone_dispute(
+        ctx: Context<ResolveMilestoneDispute>,
+        release_to_recipient: bool,
+    ) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let arbiter = ctx.accounts.arbiter.key;
+
+        require!(ctx.accounts.milestone.disputed, EscrowError::NotDisputed);
+        require_keys_eq!(
+            *arbiter,
+            ctx.accounts.milestone.arbiter_for(escrow_state),
+            EscrowError::InvalidArbiter
+        );
+        let now = Clock::get()?.unix_timestamp;
+        if release_to_recipient {
+            require!(
+                now >= escrow_state.release_not_before,
+                EscrowError::ReleaseNotYetAllowed
+            );
+        }
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+        let amount = ctx.accounts.milestone.amount;
+        let remaining = escrow_state
+            .amount
+            .checked_sub(amount)
+            .ok_or(EscrowError::InvalidMilestone)?;
+        let retained_amount = if release_to_recipient {
+            escrow_state.retainage(amount)?
+        } else {
+            0
+        };
+        let payout = amount
+            .checked_sub(retained_amount)
+            .ok_or(EscrowError::Overflow)?;
+
+        let address_seed = escrow_state.address_seed();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
+        ]];
+        let to = if release_to_recipient {
+            ctx.accounts.recipient_deposit_token_account.to_account_info()
+        } else {
+            ctx.accounts.initializer_refund_token_account.to_account_info()
+        };
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to,
+            authority: escrow_state.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx =
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer_checked(cpi_ctx, payout, ctx.accounts.mint.decimals)?;
+
+        let milestone = &mut ctx.accounts.milestone;
+        milestone.released = true;
+        milestone.disputed = false;
+        escrow_state.disputed_milestones = escrow_state
+            .disputed_milestones
+            .checked_sub(1)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.amount = remaining;
+        escrow_state.funded_amount = remaining;
+        escrow_state.milestone_total = escrow_state
+            .milestone_total
+            .checked_sub(amount)
+            .ok_or(EscrowError::Overflow)?;
+        if release_to_recipient {
+            escrow_state.released_amount = escrow_state
+                .released_amount
+                .checked_add(amount)
+                .ok_or(EscrowError::Overflow)?;
+            escrow_state.retained_amount = escrow_state
+                .retained_amount
+                .checked_add(retained_amount)
+                .ok_or(EscrowError::Overflow)?;
+            escrow_state.warranty_ends_at = now
+                .checked_add(escrow_state.warranty_period)
+                .ok_or(EscrowError::Overflow)?;
+        }
+        ctx.accounts.mint_stats.record_release(payout);
+        if remaining == 0 {
+            let status = if release_to_recipient {
+                EscrowStatus::Withdrawn
+            } else {
+                EscrowStatus::Refunded
+            };
+            escrow_state.set_status(status, arbiter, payout);
+            ctx.accounts.mint_stats.record_settled();
+        }
+
+        emit!(MilestoneDisputeResolved {
+            escrow: escrow_state.key(),
+            index: milestone.index,
+            arbiter: *arbiter,
+            release_to_recipient,
+            amount: payout,
+            retained_amount,
+            remaining,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Turns the escrow into an OTC trade: the recipient no longer withdraws
+    /// the deposit but buys it with `exchange`, paying `taker_amount` of
+    /// `taker_mint` to the initializer. Open the trade in the same transaction
+    /// as the deposit so the recipient cannot withdraw in between.
+    pub fn open_trade(ctx: Context<OpenTrade>, taker_amount: u64) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Funding | EscrowStatus::Initialized
+            ),
+            EscrowError::InvalidState
+        );
+        // Every condition `withdraw` would check has to be met by `exchange`
+        // instead, so the trade only supports escrows that pay out in one go.
+        require!(
+            taker_amount > 0
+                && !escrow_state.crowdfunded
+                && escrow_state.milestone_count == 0
+                && escrow_state.retainage_bps == 0
+                && escrow_state.governance_proposal == Pubkey::default()
+                && escrow_state.withdraw_cosigner == Pubkey::default(),
+            EscrowError::InvalidTrade
+        );
+        escrow_state.trade_mode = true;
+
+        let trade = &mut ctx.accounts.trade;
+        trade.escrow = escrow_state.key();
+        trade.taker_mint = ctx.accounts.taker_mint.key();
+        trade.taker_amount = taker_amount;
+        trade.bump = ctx.bumps.trade;
+        trade.original_amount = escrow_state.amount;
+        trade.original_taker_amount = taker_amount;
+
+        emit!(TradeOpened {
+            escrow: escrow_state.key(),
+            amount: escrow_state.amount,
+            taker_mint: trade.taker_mint,
+            taker_amount,
+        });
+
+        Ok(())
+    }
+
+    /// Fills a trade escrow: the recipient buys `amount` of the deposit and
+    /// pays the initializer its share of the trade's `taker_amount` in the
+    /// same instruction. Every fill is charged at the price set by
+    /// `open_trade`, rounded up to a whole `taker_mint` token in the
+    /// initializer's favour, and the fill that empties the escrow pays the
+    /// rest of `taker_amount`, so the fills add up to exactly the original
+    /// payment. The initializer can `cancel` to take back whatever is still
+    /// unfilled.
+    pub fn exchange(ctx: Context<Exchange>, amount: u64) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let recipient = &ctx.accounts.recipient;
+        let trade = &mut ctx.accounts.trade;
+
+        require!(
+            escrow_state.status == EscrowStatus::Initialized,
+            EscrowError::InvalidState
+        );
+        require!(escrow_state.trade_mode, EscrowError::InvalidTrade);
+        require!(
+            amount > 0 && amount <= escrow_state.amount,
+            EscrowError::InvalidAmount
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < escrow_state.timeout, EscrowError::TimeoutExpired);
+        require!(
+            now >= escrow_state.release_not_before,
+            EscrowError::ReleaseNotYetAllowed
+        );
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+        let remaining = escrow_state.amount - amount;
+        let payment = if remaining == 0 {
+            // The last fill pays whatever is left, so rounding never leaves
+            // the initializer short or the trade with dust.
+            trade.taker_amount
+        } else {
+            let price = trade.original_taker_amount as u128 * amount as u128;
+            let payment = price.div_ceil(trade.original_amount as u128);
+            u64::try_from(payment)
+                .map_err(|_| EscrowError::Overflow)?
+                .min(trade.taker_amount)
+        };
+
+        // Transfer the payment from the recipient to the initializer.
+        let cpi_accounts = TransferChecked {
+            from: ctx
+                .accounts
+                .recipient_payment_token_account
+                .to_account_info(),
+            mint: ctx.accounts.taker_mint.to_account_info(),
+            to: ctx
+                .accounts
+                .initializer_receive_token_account
+                .to_account_info(),
+            authority: recipient.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer_checked(cpi_ctx, payment, ctx.accounts.taker_mint.decimals)?;
+
+        // Transfer the filled part of the deposit from the vault to the
+        // recipient.
+        let address_seed = escrow_state.address_seed();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
+        ]];
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx
+                .accounts
+                .recipient_deposit_token_account
+                .to_account_info(),
+            authority: escrow_state.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx =
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+        trade.taker_amount = trade
+            .taker_amount
+            .checked_sub(payment)
+            .ok_or(EscrowError::Overflow)?;
+        trade.filled_amount = trade
+            .filled_amount
+            .checked_add(amount)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.released_amount = escrow_state
+            .released_amount
+            .checked_add(amount)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.amount = remaining;
+        escrow_state.funded_amount = remaining;
+        ctx.accounts.mint_stats.record_release(amount);
+        if remaining == 0 {
+            escrow_state.set_status(EscrowStatus::Withdrawn, recipient.key, amount);
+            ctx.accounts.mint_stats.record_settled();
+        }
+
+        emit!(EscrowExchanged {
+            escrow: escrow_state.key(),
+            recipient: *recipient.key,
+            amount,
+            taker_mint: trade.taker_mint,
+            taker_amount: payment,
+            remaining,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Gives the initializer's final sign-off on a settled escrow, paying the
+    /// retained share out to the recipient.
+    pub fn release_retainage(ctx: Context<ReleaseRetainage>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            escrow_state.is_settled() && escrow_state.retained_amount > 0,
+            EscrowError::NoRetainage
+        );
+
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+
+        let address_seed = escrow_state.address_seed();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
+        ]];
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx
+                .accounts
+                .recipient_deposit_token_account
+                .to_account_info(),
+            authority: escrow_state.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx =
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        let amount = escrow_state.retained_amount;
+        token::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+        ctx.accounts.mint_stats.record_release(amount);
+
+        escrow_state.retained_amount = 0;
+
+        emit!(RetainageReleased {
+            escrow: escrow_state.key(),
+            amount,
+            released_to_recipient: true,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the arbiter settle the retained share once the escrow is settled
+    /// and the warranty period has ended.
+    pub fn resolve_retainage(ctx: Context<ResolveRetainage>, release_to_recipient: bool) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            escrow_state.is_settled() && escrow_state.retained_amount > 0,
+            EscrowError::NoRetainage
+        );
+        require!(
+            escrow_state.arbiter_accepted,
+            EscrowError::ArbiterNotAccepted
+        );
+        require!(
+            Clock::get()?.unix_timestamp >= escrow_state.warranty_ends_at,
+            EscrowError::WarrantyPeriodActive
+        );
+
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+
+        let address_seed = escrow_state.address_seed();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
+        ]];
+        let to = if release_to_recipient {
+            ctx.accounts.recipient_deposit_token_account.to_account_info()
+        } else {
+            ctx.accounts.initializer_refund_token_account.to_account_info()
+        };
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to,
+            authority: escrow_state.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx =
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        let amount = escrow_state.retained_amount;
+        token::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+        ctx.accounts.mint_stats.record_release(amount);
+
+        escrow_state.retained_amount = 0;
+
+        emit!(RetainageReleased {
+            escrow: escrow_state.key(),
+            amount,
+            released_to_recipient: release_to_recipient,
+        });
+
+        Ok(())
+    }
+
+    /// Creates an escrow template, letting a marketplace apply the same
+    /// policy to every escrow of a `category`: the review window, the
+    /// retainage and its warranty period, and the fee cap for listed cases.
+    /// Escrows opt in by passing the template when they are created.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_template(
+        ctx: Context<CreateTemplate>,
+        template_id: u64,
+        category: EscrowCategory,
+        dispute_window: i64,
+        retainage_bps: u16,
+        warranty_period: i64,
+        max_arbiter_fee_bps: u16,
+    ) -> Result<()> {
+        let template = &mut ctx.accounts.template;
+        template.authority = ctx.accounts.authority.key();
+        template.template_id = template_id;
+        template.bump = ctx.bumps.template;
+        template.record_parameters(
+            category,
+            dispute_window,
+            retainage_bps,
+            warranty_period,
+            max_arbiter_fee_bps,
+        )?;
+
+        emit!(TemplateUpdated {
+            template: template.key(),
+            authority: template.authority,
+            category,
+            dispute_window,
+            retainage_bps,
+            warranty_period,
+            max_arbiter_fee_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Changes the parameters of a template. Escrows already created from it
+    /// keep the parameters they were created with.
+    pub fn update_template(
+        ctx: Context<UpdateTemplate>,
+        category: EscrowCategory,
+        dispute_window: i64,
+        retainage_bps: u16,
+        warranty_period: i64,
+        max_arbiter_fee_bps: u16,
+    ) -> Result<()> {
+        let template = &mut ctx.accounts.template;
+        template.record_parameters(
+            category,
+            dispute_window,
+            retainage_bps,
+            warranty_period,
+            max_arbiter_fee_bps,
+        )?;
+
+        emit!(TemplateUpdated {
+            template: template.key(),
+            authority: template.authority,
+            category,
+            dispute_window,
+            retainage_bps,
+            warranty_period,
+            max_arbiter_fee_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Creates the `MintStats` account of a mint whose escrows all predate
+    /// it, so that they can still settle. Anyone may pay for it.
+    pub fn init_mint_stats(ctx: Context<InitMintStats>) -> Result<()> {
+        let mint_stats = &mut ctx.accounts.mint_stats;
+        mint_stats.mint = ctx.accounts.mint.key();
+        mint_stats.bump = ctx.bumps.mint_stats;
+
+        Ok(())
+    }
+
+    /// Grows an escrow account written before `Escrow` last grew to
+    /// `Escrow::LEN`, topping up its rent from `payer`. The added bytes are
+    /// zeroed, so the fields they hold decode as their all-zero value, except
+    /// that an escrow from before arbiters had to accept the role gives its
+    /// arbiter a fresh `ARBITER_ACCEPTANCE_PERIOD` to do so.
+    ///
+    /// Escrows from before the layout was reordered are rewritten field by
+    /// field. They do not record their mint, so they also need their
+    /// `b"vault"` token account as `legacy_vault`; `migrate_vault` then moves
+    /// its tokens.
+    pub fn migrate_escrow(ctx: Context<MigrateEscrow>) -> Result<()> {
+        let escrow_info = ctx.accounts.escrow_state.to_account_info();
+        let new_len = 8 + Escrow::LEN;
+
+        let legacy = {
+            let data = escrow_info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == *Escrow::DISCRIMINATOR,
+                ErrorCode::AccountDiscriminatorMismatch
+            );
+            require!(data.len() < new_len, EscrowError::AlreadyMigrated);
+            if data.len() == 8 + LegacyEscrow::LEN {
+                Some(LegacyEscrow::deserialize(&mut &data[8..])?)
+            } else {
+                None
+            }
+        };
+        let legacy = match legacy {
+            Some(legacy) => {
+                let legacy_vault = ctx
+                    .accounts
+                    .legacy_vault
+                    .as_ref()
+                    .ok_or(EscrowError::InvalidLegacyVault)?;
+                let legacy_vault_address = Pubkey::create_program_address(
+                    &[b"vault", escrow_info.key.as_ref(), &[legacy.vault_bump]],
+                    &crate::ID,
+                )
+                .map_err(|_| EscrowError::InvalidLegacyVault)?;
+                require_keys_eq!(
+                    legacy_vault.key(),
+                    legacy_vault_address,
+                    EscrowError::InvalidLegacyVault
+                );
+                Some(legacy.upgrade(legacy_vault.mint))
+            }
+            None => None,
+        };
+
+        let shortfall = Rent::get()?
+            .minimum_balance(new_len)
+            .saturating_sub(escrow_info.lamports());
+        if shortfall > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.payer.to_account_info(),
+                to: escrow_info.clone(),
+            };
+            let cpi_ctx =
+                CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+            system_program::transfer(cpi_ctx, shortfall)?;
+        }
+        escrow_info.resize(new_len)?;
+
+        let mut escrow = match legacy {
+            Some(escrow) => escrow,
+            None => Escrow::try_deserialize(&mut &escrow_info.try_borrow_data()?[..])?,
+        };
+        if !escrow.arbiter_accepted && escrow.arbiter_acceptance_deadline == 0 {
+            escrow.arbiter_acceptance_deadline = Clock::get()?
+                .unix_timestamp
+                .checked_add(ARBITER_ACCEPTANCE_PERIOD)
+                .ok_or(EscrowError::Overflow)?;
+        }
+        escrow.try_serialize(&mut &mut escrow_info.try_borrow_mut_data()?[..])?;
+
+        Ok(())
+    }
+
+    /// Moves the tokens of an escrow created while the vault was still a PDA
+    /// seeded with `b"vault"` into the escrow's associated token account, then
+    /// closes the legacy vault and returns its rent to the escrow's rent
+    /// collector.
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        require!(
+            escrow_state.vault_bump != 0,
+            EscrowError::VaultAlreadyMigrated
+        );
+
+        let escrow_key = escrow_state.key();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"vault".as_ref(),
+            escrow_key.as_ref(),
+            &[escrow_state.vault_bump],
+        ]];
+        let amount = ctx.accounts.legacy_vault.amount;
+        if amount > 0 {
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.legacy_vault.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx.accounts.vault.to_account_info(),
+                authority: ctx.accounts.legacy_vault.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx =
+                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+        }
+
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.legacy_vault.to_account_info(),
+            destination: ctx.accounts.rent_collector.to_account_info(),
+            authority: ctx.accounts.legacy_vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::close_account(cpi_ctx)?;
+
+        escrow_state.vault_bump = 0;
+
+        emit!(VaultMigrated {
+            escrow: escrow_key,
+            vault: ctx.accounts.vault.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the recovery key named at `initialize` refund an escrow to its
+    /// registered token account once `RECOVERY_DELAY` has passed after the
+    /// timeout without the initializer refunding it.
+    pub fn recover_refund(ctx: Context<RecoverRefund>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            escrow_state.status == EscrowStatus::Initialized,
+            EscrowError::InvalidState
+        );
+        let recoverable_at = escrow_state
+            .timeout
+            .checked_add(RECOVERY_DELAY)
+            .ok_or(EscrowError::Overflow)?;
+        require!(
+            Clock::get()?.unix_timestamp >= recoverable_at,
+            EscrowError::RefundNotAllowed
+        );
+
+        require!(
+            escrow_state.disputed_milestones == 0,
+            EscrowError::MilestoneDisputeOpen
+        );
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+
+        let address_seed = escrow_state.address_seed();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
+        ]];
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.recovery_token_account.to_account_info(),
+            authority: escrow_state.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx =
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer_checked(cpi_ctx, escrow_state.amount, ctx.accounts.mint.decimals)?;
+
+        let amount = escrow_state.amount;
+        escrow_state.set_status(
+            EscrowStatus::Refunded,
+            ctx.accounts.recovery_authority.key,
+            amount,
+        );
+        ctx.accounts.mint_stats.record_release(amount);
+        ctx.accounts.mint_stats.record_settled();
+
+        emit!(EscrowRecovered {
+            escrow: escrow_state.key(),
+            recovery_authority: *ctx.accounts.recovery_authority.key,
+            amount: escrow_state.amount,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Allows the initializer to get a refund after the timeout has expired,
+    /// or after the funding deadline of an escrow that missed its funding goal.
+    ///
+    /// The initializer may be a PDA, such as the native treasury of an
+    /// spl-governance governance, signing through the CPI with which its
+    /// program executes a passed proposal. The refund can only go to a token
+    /// account the initializer owns, such as the treasury's ATA.
+    pub fn refund(ctx: Context<Refund>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let initializer = &ctx.accounts.initializer;
+
+        let now = Clock::get()?.unix_timestamp;
+        let refund_amount = match escrow_state.status {
+            EscrowStatus::Initialized => {
+                require!(now >= escrow_state.timeout, EscrowError::RefundNotAllowed);
+                escrow_state.amount
+            }
+            EscrowStatus::Funding => {
+                require!(
+                    escrow_state.missed_funding_goal(now),
+                    EscrowError::RefundNotAllowed
+                );
+                escrow_state.funded_amount
+            }
+            _ => return err!(EscrowError::InvalidState),
+        };
+        // Contributors to a crowdfunded escrow claim their own share back.
+        let refund_amount = if escrow_state.crowdfunded { 0 } else { refund_amount };
+
+        require!(
+            escrow_state.disputed_milestones == 0,
+            EscrowError::MilestoneDisputeOpen
+        );
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+
+        // Transfer tokens from the vault back to the initializer.
+        if refund_amount > 0 {
+            let address_seed = escrow_state.address_seed();
+            let signer_seeds: &[&[&[u8]]] = &[&[
+                b"escrow".as_ref(),
+                escrow_state.initializer.as_ref(),
+                address_seed.as_ref(),
+                &[escrow_state.escrow_bump],
+            ]];
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.vault.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx
+                    .accounts
+                    .initializer_refund_token_account
+                    .to_account_info(),
+                authority: escrow_state.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx =
+                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer_checked(cpi_ctx, refund_amount, ctx.accounts.mint.decimals)?;
+        }
+
+        escrow_state.set_status(EscrowStatus::Refunded, initializer.key, refund_amount);
+        ctx.accounts.mint_stats.record_release(refund_amount);
+        ctx.accounts.mint_stats.record_settled();
+
+        emit!(EscrowRefunded {
+            escrow: escrow_state.key(),
+            initializer: *initializer.key,
+            amount: refund_amount,
+            external_id: escrow_state.external_id,
+            auditor: escrow_state.auditor,
+            template: escrow_state.template,
+        });
+
+        Ok(())
+    }
+
+    /// Allows the initializer to cancel the escrow before timeout, or at any
+    /// time while it is still waiting to be funded. As with `refund`, a PDA
+    /// initializer signs through CPI and is paid into a token account it owns.
+    pub fn cancel(ctx: Context<Cancel>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let initializer = &ctx.accounts.initializer;
+
+        let refund_amount = match escrow_state.status {
+            // Return whatever has been deposited so far.
+            EscrowStatus::Funding => escrow_state.funded_amount,
+            EscrowStatus::Initialized => {
+                require!(
+                    Clock::get()?.unix_timestamp < escrow_state.timeout,
+                    EscrowError::CancelNotAllowed
+                );
+                escrow_state.amount
+            }
+            _ => return err!(EscrowError::InvalidState),
+        };
+        // Contributors to a crowdfunded escrow claim their own share back.
+        let refund_amount = if escrow_state.crowdfunded { 0 } else { refund_amount };
+
+        require!(
+            escrow_state.disputed_milestones == 0,
+            EscrowError::MilestoneDisputeOpen
+        );
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+
+        // Transfer tokens from the vault back to the initializer.
+        if refund_amount > 0 {
+            let address_seed = escrow_state.address_seed();
+            let signer_seeds: &[&[&[u8]]] = &[&[
+                b"escrow".as_ref(),
+                escrow_state.initializer.as_ref(),
+                address_seed.as_ref(),
+                &[escrow_state.escrow_bump],
+            ]];
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.vault.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx
+                    .accounts
+                    .initializer_refund_token_account
+                    .to_account_info(),
+                authority: escrow_state.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx =
+                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer_checked(cpi_ctx, refund_amount, ctx.accounts.mint.decimals)?;
+        }
+
+        escrow_state.set_status(EscrowStatus::Cancelled, initializer.key, refund_amount);
+        ctx.accounts.mint_stats.record_release(refund_amount);
+        ctx.accounts.mint_stats.record_settled();
+
+        emit!(EscrowCancelled {
+            escrow: escrow_state.key(),
+            initializer: *initializer.key,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Closes a settled escrow and its empty vault, returning the rent of both
+    /// to the escrow's rent collector, which is the initializer unless another
+    /// account was designated at creation. Crowdfunded escrows and escrows with milestones or
+    /// a trade stay open because their receipts, milestones and trade terms
+    /// are derived from the escrow address.
+    pub fn close_escrow(ctx: Context<CloseEscrow>) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Withdrawn | EscrowStatus::Refunded | EscrowStatus::Cancelled
+            ),
+            EscrowError::InvalidState
+        );
+        require!(!escrow_state.crowdfunded, EscrowError::CrowdfundedEscrow);
+        require!(
+            escrow_state.milestone_count == 0 && !escrow_state.trade_mode,
+            EscrowError::MilestonesOutstanding
+        );
+        require!(
+            escrow_state.expected_vault_balance() == 0 && ctx.accounts.vault.amount == 0,
+            EscrowError::VaultNotEmpty
+        );
+
+        let address_seed = escrow_state.address_seed();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
+        ]];
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.vault.to_account_info(),
+            destination: ctx.accounts.rent_collector.to_account_info(),
+            authority: escrow_state.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::close_account(cpi_ctx)?;
+
+        emit!(EscrowClosed {
+            escrow: escrow_state.key(),
+            initializer: *ctx.accounts.initializer.key,
+            external_id: escrow_state.external_id,
+            rent_collector: *ctx.accounts.rent_collector.key,
+        });
+
+        Ok(())
+    }
+
+    /// Replaces an escrow with a successor on the same parties and terms, with
+    /// a fresh timeout and possibly a new `amount`. The successor is derived
+    /// from `external_id` like an escrow initialized with `order_seeded`, and
+    /// records the escrow it replaces in `previous_escrow`.
+    ///
+    /// The successor keeps the previous escrow's release conditions, including
+    /// its governance proposal, release date and heartbeat settings. The
+    /// arbiter has to accept the role again, and `invoice_hash` starts empty
+    /// since the new period is billed on a new invoice.
+    ///
+    /// An escrow still `Initialized` is cancelled and its deposit carried
+    /// over: whatever the successor needs beyond it comes from the
+    /// initializer, and whatever it does not need goes back to them. A settled
+    /// escrow is simply succeeded and the successor funded in full.
+    pub fn renew(ctx: Context<Renew>, amount: u64, external_id: u64) -> Result<()> {
+        let previous = &mut ctx.accounts.escrow_state;
+        let initializer = &ctx.accounts.initializer;
+
+        require!(
+            matches!(
+                previous.status,
+                EscrowStatus::Initialized
+                    | EscrowStatus::Withdrawn
+                    | EscrowStatus::Refunded
+                    | EscrowStatus::Cancelled
+            ),
+            EscrowError::InvalidState
+        );
+        // Receipts, milestones and trade terms are tied to the old address.
+        require!(
+            !previous.crowdfunded && previous.milestone_count == 0 && !previous.trade_mode,
+            EscrowError::NotRenewable
+        );
+        let carried = if previous.status == EscrowStatus::Initialized {
+            previous.amount
+        } else {
+            0
+        };
+
+        let successor = &mut ctx.accounts.successor;
+        successor.record_terms(
+            previous.mint,
+            previous.initializer,
+            previous.recipient,
+            previous.arbiter,
+            amount,
+            previous.base_duration(),
+            previous.terms_hash,
+            previous.warning_window().min(previous.base_duration()),
+            previous.dispute_window,
+        )?;
+        successor.backup_arbiter = previous.backup_arbiter;
+        successor.arbiter_registrar = previous.arbiter_registrar;
+        successor.required_qualifications = previous.required_qualifications;
+        successor.max_arbiter_fee_bps = previous.max_arbiter_fee_bps;
+        successor.arbiter_fee_bps = previous.arbiter_fee_bps;
+        successor.template = previous.template;
+        successor.governance_proposal = previous.governance_proposal;
+        successor.governance_program = previous.governance_program;
+        successor.release_not_before = previous.release_not_before;
+        successor.heartbeat_increment = previous.heartbeat_increment;
+        successor.heartbeat_max_duration = previous.heartbeat_max_duration;
+        successor.retainage_bps = previous.retainage_bps;
+        successor.warranty_period = previous.warranty_period;
+        successor.withdraw_cosigner = previous.withdraw_cosigner;
+        successor.recovery_authority = previous.recovery_authority;
+        successor.recovery_token_account = previous.recovery_token_account;
+        successor.settlement_guard = previous.settlement_guard;
+        successor.auditor = previous.auditor;
+        successor.mint_freezable = previous.mint_freezable;
+        successor.external_id = external_id;
+        successor.order_seeded = true;
+        successor.previous_escrow = previous.key();
+        successor.funded_amount = amount;
+        successor.min_goal = amount;
+        successor.escrow_bump = ctx.bumps.successor;
+        successor.activate(initializer.key)?;
+
+        if carried > 0 {
+            check_settlement_guard(previous, ctx.accounts.instructions_sysvar.as_ref())?;
+            let address_seed = previous.address_seed();
+            let signer_seeds: &[&[&[u8]]] = &[&[
+                b"escrow".as_ref(),
+                previous.initializer.as_ref(),
+                address_seed.as_ref(),
+                &[previous.escrow_bump],
+            ]];
+            let moved = carried.min(amount);
+            for (to, amount) in [
+                (ctx.accounts.successor_vault.to_account_info(), moved),
+                (
+                    ctx.accounts.initializer_token_account.to_account_info(),
+                    carried - moved,
+                ),
+            ] {
+                if amount == 0 {
+                    continue;
+                }
+                let cpi_accounts = TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to,
+                    authority: previous.to_account_info(),
+                };
+                let cpi_program = ctx.accounts.token_program.to_account_info();
+                let cpi_ctx =
+                    CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+                token::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+            }
+            previous.set_status(EscrowStatus::Cancelled, initializer.key, carried);
+            ctx.accounts.mint_stats.record_release(carried - moved);
+            ctx.accounts.mint_stats.record_settled();
+
+            emit!(EscrowCancelled {
+                escrow: previous.key(),
+                initializer: *initializer.key,
+                external_id: previous.external_id,
+            });
+        }
+        if amount > carried {
+            // Transfer the rest of the amount from initializer to the vault.
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.initializer_token_account.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx.accounts.successor_vault.to_account_info(),
+                authority: initializer.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+            token::transfer_checked(cpi_ctx, amount - carried, ctx.accounts.mint.decimals)?;
+        }
+        let mint_stats = &mut ctx.accounts.mint_stats;
+        mint_stats.record_opened(ctx.accounts.mint.key(), ctx.bumps.mint_stats);
+        // Carried tokens stay locked and were already counted when deposited.
+        mint_stats.record_deposit(amount.saturating_sub(carried));
+
+        emit!(EscrowRenewed {
+            escrow: successor.key(),
+            previous_escrow: successor.previous_escrow,
+            initializer: *initializer.key,
+            amount,
+            carried,
+            timeout: successor.timeout,
+            external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the recipient decline an escrow they never agreed to, at any time
+    /// before it has been settled. Whatever was deposited goes straight back
+    /// to the initializer and the escrow is marked `Refunded`, so contributors
+    /// to a crowdfunded escrow can claim their share. A recipient who already
+    /// took a milestone or a fill has agreed to the escrow and cannot decline.
+    pub fn decline(ctx: Context<Decline>, reason_code: u8) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let recipient = &ctx.accounts.recipient;
+
+        let refund_amount = match escrow_state.status {
+            EscrowStatus::Funding => escrow_state.funded_amount,
+            EscrowStatus::Initialized => escrow_state.amount,
+            _ => return err!(EscrowError::InvalidState),
+        };
+        require!(
+            escrow_state.released_amount == 0,
+            EscrowError::PartiallyReleased
+        );
+        // Contributors to a crowdfunded escrow claim their own share back.
+        let refund_amount = if escrow_state.crowdfunded { 0 } else { refund_amount };
+
+        require!(
+            escrow_state.disputed_milestones == 0,
+            EscrowError::MilestoneDisputeOpen
+        );
+        check_settlement_guard(escrow_state, ctx.accounts.instructions_sysvar.as_ref())?;
+
+        // Transfer tokens from the vault back to the initializer.
+        if refund_amount > 0 {
+            let address_seed = escrow_state.address_seed();
+            let signer_seeds: &[&[&[u8]]] = &[&[
+                b"escrow".as_ref(),
+                escrow_state.initializer.as_ref(),
+                address_seed.as_ref(),
+                &[escrow_state.escrow_bump],
+            ]];
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.vault.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx
+                    .accounts
+                    .initializer_refund_token_account
+                    .to_account_info(),
+                authority: escrow_state.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx =
+                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer_checked(cpi_ctx, refund_amount, ctx.accounts.mint.decimals)?;
+        }
+
+        escrow_state.set_status(EscrowStatus::Refunded, recipient.key, refund_amount);
+        ctx.accounts.mint_stats.record_release(refund_amount);
+        ctx.accounts.mint_stats.record_settled();
+
+        emit!(EscrowDeclined {
+            escrow: escrow_state.key(),
+            recipient: *recipient.key,
+            amount: refund_amount,
+            reason_code,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Registers `arbiter` with `registrar`, such as a marketplace vouching
+    /// for its arbiters, or updates an existing registration. The registrar
+    /// attests the `qualifications`, a bitmask whose meaning it defines, and
+    /// the arbiter sets the `fee_bps` they charge, so both sign.
+    pub fn register_arbiter(
+        ctx: Context<RegisterArbiter>,
+        qualifications: u32,
+        fee_bps: u16,
+    ) -> Result<()> {
+        require!(fee_bps <= MAX_BPS, EscrowError::InvalidArbiterFee);
+
+        let registration = &mut ctx.accounts.registration;
+        registration.registrar = ctx.accounts.registrar.key();
+        registration.arbiter = ctx.accounts.arbiter.key();
+        registration.qualifications = qualifications;
+        registration.fee_bps = fee_bps;
+        registration.bump = ctx.bumps.registration;
+
+        emit!(ArbiterRegistered {
+            registrar: registration.registrar,
+            arbiter: registration.arbiter,
+            qualifications,
+            fee_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Closes an arbiter's registration, returning its rent to the registrar.
+    /// Either the registrar or the arbiter may call it. Cases the arbiter
+    /// already claimed are not affected.
+    pub fn deregister_arbiter(ctx: Context<DeregisterArbiter>) -> Result<()> {
+        emit!(ArbiterDeregistered {
+            registrar: ctx.accounts.registration.registrar,
+            arbiter: ctx.accounts.registration.arbiter,
+        });
+
+        Ok(())
+    }
+
+    /// Lets an arbiter registered with the escrow's `arbiter_registrar` take
+    /// on an escrow created without one. The first arbiter holding every
+    /// `required_qualifications` bit and charging at most
+    /// `max_arbiter_fee_bps` gets the case; claiming it counts as accepting
+    /// the role. The fee is recorded in `arbiter_fee_bps` for the parties to
+    /// settle; the program does not deduct it.
+    pub fn claim_case(ctx: Context<ClaimCase>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let registration = &ctx.accounts.registration;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Funding | EscrowStatus::Initialized
+            ),
+            EscrowError::InvalidState
+        );
+        require!(
+            escrow_state.arbiter == Pubkey::default()
+                && escrow_state.arbiter_registrar != Pubkey::default(),
+            EscrowError::CaseNotListed
+        );
+        require!(
+            registration.arbiter != escrow_state.initializer
+                && registration.arbiter != escrow_state.recipient,
+            EscrowError::InvalidArbiter
+        );
+        require!(
+            registration.qualifications & escrow_state.required_qualifications
+                == escrow_state.required_qualifications,
+            EscrowError::ArbiterNotQualified
+        );
+        require!(
+            registration.fee_bps <= escrow_state.max_arbiter_fee_bps,
+            EscrowError::InvalidArbiterFee
+        );
+
+        escrow_state.arbiter = registration.arbiter;
+        escrow_state.arbiter_accepted = true;
+        escrow_state.arbiter_fee_bps = registration.fee_bps;
+
+        emit!(CaseClaimed {
+            escrow: escrow_state.key(),
+            arbiter: registration.arbiter,
+            fee_bps: registration.fee_bps,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the named arbiter accept the role. Escrows created with
+    /// `create_escrow` cannot be funded until the arbiter has accepted, and an
+    /// arbiter who has not accepted cannot resolve.
+    pub fn accept_arbiter_role(ctx: Context<AcceptArbiterRole>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Funding
+                    | EscrowStatus::Initialized
+                    | EscrowStatus::Disputed
+            ),
+            EscrowError::InvalidState
+        );
+        require!(
+            !escrow_state.arbiter_accepted,
+            EscrowError::ArbiterAlreadyAccepted
+        );
+        require!(
+            Clock::get()?.unix_timestamp < escrow_state.arbiter_acceptance_deadline,
+            EscrowError::ArbiterAcceptanceExpired
+        );
+
+        escrow_state.arbiter_accepted = true;
+
+        emit!(ArbiterAccepted {
+            escrow: escrow_state.key(),
+            arbiter: *ctx.accounts.arbiter.key,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the initializer and recipient jointly name a new arbiter after
+    /// the current one let the acceptance deadline pass. The new arbiter gets
+    /// a fresh acceptance period.
+    pub fn appoint_arbiter(ctx: Context<AppointArbiter>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Funding
+                    | EscrowStatus::Initialized
+                    | EscrowStatus::Disputed
+            ),
+            EscrowError::InvalidState
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            !escrow_state.arbiter_accepted && now >= escrow_state.arbiter_acceptance_deadline,
+            EscrowError::ArbiterAppointmentActive
+        );
+        require!(
+            !escrow_state.backup_arbiter_due(now),
+            EscrowError::ReplacementWindowClosed
+        );
+
+        let previous_arbiter = escrow_state.arbiter;
+        escrow_state.arbiter = ctx.accounts.new_arbiter.key();
+        escrow_state.arbiter_fee_bps = 0;
+        escrow_state.arbiter_acceptance_deadline = now
+            .checked_add(ARBITER_ACCEPTANCE_PERIOD)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.replacement_deadline = 0;
+
+        emit!(ArbiterAppointed {
+            escrow: escrow_state.key(),
+            previous_arbiter,
+            arbiter: escrow_state.arbiter,
+            acceptance_deadline: escrow_state.arbiter_acceptance_deadline,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the initializer and recipient jointly name a backup arbiter, who
+    /// takes over if the arbiter resigns and no replacement is appointed in
+    /// time.
+    pub fn set_backup_arbiter(ctx: Context<SetBackupArbiter>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Funding
+                    | EscrowStatus::Initialized
+                    | EscrowStatus::Disputed
+            ),
+            EscrowError::InvalidState
+        );
+
+        escrow_state.backup_arbiter = ctx.accounts.backup_arbiter.key();
+
+        emit!(BackupArbiterSet {
+            escrow: escrow_state.key(),
+            backup_arbiter: escrow_state.backup_arbiter,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the initializer and recipient jointly name an auditor. The auditor
+    /// cannot move funds; they can only call `request_audit_snapshot`. Naming
+    /// the default pubkey removes the auditor.
+    pub fn set_auditor(ctx: Context<SetAuditor>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.auditor = ctx.accounts.auditor.key();
+
+        emit!(AuditorSet {
+            escrow: escrow_state.key(),
+            auditor: escrow_state.auditor,
+        });
+
+        Ok(())
+    }
+
+    /// Lets an arbiter who accepted the role step down. Resolution is frozen
+    /// until the parties appoint a replacement with `appoint_arbiter`, or the
+    /// replacement window closes and the backup arbiter takes over. An
+    /// arbiter can only walk away from a dispute once a backup arbiter is
+    /// named: `appoint_arbiter` needs both parties, and the one who is
+    /// winning the dispute could otherwise keep the funds locked for good.
+    pub fn resign_arbiter(ctx: Context<ResignArbiter>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Funding
+                    | EscrowStatus::Initialized
+                    | EscrowStatus::Disputed
+            ),
+            EscrowError::InvalidState
+        );
+        require!(
+            escrow_state.arbiter_accepted,
+            EscrowError::ArbiterNotAccepted
+        );
+        require!(
+            escrow_state.status != EscrowStatus::Disputed
+                || escrow_state.backup_arbiter != Pubkey::default(),
+            EscrowError::BackupArbiterRequired
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        escrow_state.arbiter_accepted = false;
+        // Lapse the appointment right away so the parties can replace them.
+        escrow_state.arbiter_acceptance_deadline = now;
+        escrow_state.replacement_deadline = now
+            .checked_add(ARBITER_REPLACEMENT_PERIOD)
+            .ok_or(EscrowError::Overflow)?;
+
+        emit!(ArbiterResigned {
+            escrow: escrow_state.key(),
+            arbiter: *ctx.accounts.arbiter.key,
+            backup_arbiter: escrow_state.backup_arbiter,
+            replacement_deadline: escrow_state.replacement_deadline,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the backup arbiter take over once the replacement window after a
+    /// resignation has closed without a new appointment.
+    pub fn assume_backup_arbiter(ctx: Context<AssumeBackupArbiter>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+
+        require!(
+            matches!(
+                escrow_state.status,
+                EscrowStatus::Funding
+                    | EscrowStatus::Initialized
+                    | EscrowStatus::Disputed
+            ),
+            EscrowError::InvalidState
+        );
+        require!(
+            escrow_state.backup_arbiter_due(Clock::get()?.unix_timestamp),
+            EscrowError::ReplacementWindowOpen
+        );
+
+        let previous_arbiter = escrow_state.arbiter;
+        escrow_state.arbiter = escrow_state.backup_arbiter;
+        escrow_state.backup_arbiter = Pubkey::default();
+        escrow_state.arbiter_fee_bps = 0;
+        escrow_state.arbiter_accepted = true;
+        escrow_state.replacement_deadline = 0;
+
+        emit!(BackupArbiterAssumed {
+            escrow: escrow_state.key(),
+            previous_arbiter,
+            arbiter: escrow_state.arbiter,
+        });
+
+        Ok(())
+    }
+
+    /// Allows the arbiter to resolve the dispute and release funds. Only
+    /// escrows put in `Disputed` by `raise_dispute` can be resolved.
+    /// `rationale_hash` binds the decision to the arbiter's written reasoning.
+    /// Releasing to the recipient is subject to `release_not_before`, like
+    /// `withdraw`; refunding the initializer is not.
+    pub fn resolve_by_arbiter(
+        ctx: Context<ResolveByArbiter>,
+        release_to_recipient: bool,
+        rationale_hash: [u8; 32],
+    ) -> Result<()> {
+        resolve_escrow(ctx.accounts, release_to_recipient, rationale_hash)
+    }
+
+    /// Lets the initializer or the recipient put an active escrow in dispute.
+    /// This freezes it: `withdraw`, `refund`, `cancel` and every other way of
+    /// moving the funds are closed until the arbiter resolves the dispute,
+    /// which only then becomes possible, or until the party who raised it
+    /// calls `withdraw_dispute`. The timeout stops counting down: the time
+    /// left is recorded in `dispute_time_remaining`, so raising a dispute just
+    /// before the timeout cannot let the clock decide it instead of the
+    /// arbiter. Requires an arbiter who has accepted the role, so a dispute
+    /// never locks funds with no one able to resolve.
+    pub fn raise_dispute(ctx: Context<RaiseDispute>, reason_code: u8) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let party = ctx.accounts.party.key;
+
+        require!(
+            escrow_state.status == EscrowStatus::Initialized,
+            EscrowError::InvalidState
+        );
+        require!(
+            escrow_state.arbiter_accepted,
+            EscrowError::ArbiterNotAccepted
+        );
+        require!(
+            escrow_state.disputed_milestones == 0,
+            EscrowError::MilestoneDisputeOpen
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < escrow_state.timeout, EscrowError::TimeoutExpired);
+        let amount = escrow_state.amount;
+        escrow_state.dispute_time_remaining = escrow_state.timeout - now;
+        escrow_state.dispute_raised_by = *party;
+        escrow_state.set_status(EscrowStatus::Disputed, party, amount);
+
+        emit!(DisputeRaised {
+            escrow: escrow_state.key(),
+            raised_by: *party,
+            reason_code,
+            external_id: escrow_state.external_id,
+            time_remaining: escrow_state.dispute_time_remaining,
+            raised_by_handle: handle_of(&ctx.accounts.party_profile),
+        });
+
+        Ok(())
+    }
+
+    /// Lets the party who raised a dispute drop it again, typically after
+    /// settling with the other party in private. The escrow returns to
+    /// `Initialized` and its timeout resumes with the time that was left
+    /// when the dispute was raised.
+    pub fn withdraw_dispute(ctx: Context<WithdrawDispute>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        let party = ctx.accounts.party.key;
+
+        require!(
+            escrow_state.status == EscrowStatus::Disputed,
+            EscrowError::InvalidState
+        );
+        // Disputes raised before the raiser was recorded can only be resolved.
+        require_keys_eq!(
+            escrow_state.dispute_raised_by,
+            *party,
+            EscrowError::NotDisputeRaiser
+        );
+
+        escrow_state.timeout = Clock::get()?
+            .unix_timestamp
+            .checked_add(escrow_state.dispute_time_remaining)
+            .ok_or(EscrowError::Overflow)?;
+        escrow_state.timeout_warning_emitted = false;
+        escrow_state.dispute_time_remaining = 0;
+        escrow_state.dispute_raised_by = Pubkey::default();
+        let amount = escrow_state.amount;
+        escrow_state.set_status(EscrowStatus::Initialized, party, amount);
+
+        emit!(DisputeWithdrawn {
+            escrow: escrow_state.key(),
+            withdrawn_by: *party,
+            timeout: escrow_state.timeout,
+            external_id: escrow_state.external_id,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the arbiter settle a dispute with a split: `bps_to_recipient` of
+    /// the amount goes to the recipient and the rest back to the initializer.
+    /// Crowdfunded escrows are refunded per contributor, so they cannot be
+    /// split.
+    pub fn resolve_split(
+        ctx: Context<ResolveByArbiter>,
+        bps_to_recipient: u16,
+        rationale_hash: [u8; 32],
+    ) -> Result<()> {
+        require!(bps_to_recipient <= MAX_BPS, EscrowError::InvalidSplit);
+        let recipient_amount = u64::try_from(
+            ctx.accounts.escrow_state.amount as u128 * bps_to_recipient as u128
+                / MAX_BPS as u128,
+        )
+        .map_err(|_| EscrowError::Overflow)?;
+        require_resolvable(ctx.accounts, recipient_amount > 0, rationale_hash)?;
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        require!(!escrow_state.crowdfunded, EscrowError::CrowdfundedEscrow);
+        let initializer_amount = escrow_state.amount - recipient_amount;
+
+        let address_seed = escrow_state.address_seed();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow".as_ref(),
+            escrow_state.initializer.as_ref(),
+            address_seed.as_ref(),
+            &[escrow_state.escrow_bump],
+        ]];
+
+        for (to, amount) in [
+            (
+                ctx.accounts.recipient_deposit_token_account.to_account_info(),
+                recipient_amount,
+            ),
+            (
+                ctx.accounts.initializer_refund_token_account.to_account_info(),
+                initializer_amount,
+            ),
+        ] {
+            if amount == 0 {
+                continue;
+            }
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.vault.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to,
+                authority: escrow_state.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx =
+                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+        }
+
+        let amount = escrow_state.amount;
+        let status = if recipient_amount > 0 {
+            EscrowStatus::Withdrawn
+        } else {
+            EscrowStatus::Refunded
+        };
+        escrow_state.set_status(status, ctx.accounts.arbiter.key, amount);
+        escrow_state.rationale_hash = rationale_hash;
+        ctx.accounts.mint_stats.record_release(amount);
+        ctx.accounts.mint_stats.record_settled();
+
+        emit!(EscrowSplit {
+            escrow: escrow_state.key(),
+            arbiter: *ctx.accounts.arbiter.key,
+            bps_to_recipient,
+            recipient_amount,
+            initializer_amount,
+            external_id: escrow_state.external_id,
+            rationale_hash,
+        });
+
+        Ok(())
+    }
+
+    /// Resolves several escrows of the same arbiter in one transaction.
+    ///
+    /// Each entry of `resolutions` is settled exactly like `resolve_by_arbiter`
+    /// and emits its own `EscrowResolved`. Its accounts are passed as remaining
+    /// accounts, one group of `RESOLVE_BATCH_GROUP_LEN` per entry in the order
+    /// of the `ResolveByArbiter` accounts, with the program id standing in for
+    /// an absent instructions sysvar. If any entry fails, the whole batch does.
+    pub fn resolve_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ResolveBatch<'info>>,
+        resolutions: Vec<BatchResolution>,
+    ) -> Result<()> {
+        require!(
+            !resolutions.is_empty()
+                && ctx.remaining_accounts.len() == resolutions.len() * RESOLVE_BATCH_GROUP_LEN,
+            EscrowError::InvalidBatch
+        );
+
+        let mut remaining_accounts = ctx.remaining_accounts;
+        for resolution in resolutions {
+            let mut accounts = ResolveByArbiter::try_accounts(
+                ctx.program_id,
+                &mut remaining_accounts,
+                &[],
+                &mut ResolveByArbiterBumps::default(),
+                &mut BTreeSet::new(),
+            )?;
+            require_keys_eq!(
+                accounts.arbiter.key(),
+                ctx.accounts.arbiter.key(),
+                EscrowError::InvalidArbiter
+            );
+            require_keys_eq!(
+                accounts.escrow_state.key(),
+                resolution.escrow,
+                EscrowError::InvalidBatch
+            );
+            resolve_escrow(
+                &mut accounts,
+                resolution.release_to_recipient,
+                resolution.rationale_hash,
+            )?;
+            accounts.exit(ctx.program_id)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct Cancel<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = initializer_refund_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = initializer_refund_token_account.owner == escrow_state.initializer @ EscrowError::InvalidInitializer,
+    )]
+    pub initializer_refund_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseEscrow<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        close = rent_collector,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    /// CHECK: Receives the rent of both accounts; checked against the
+    /// escrow's rent collector.
+    #[account(mut, address = escrow_state.rent_destination() @ EscrowError::InvalidRentCollector)]
+    pub rent_collector: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, external_id: u64)]
+pub struct Renew<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Box<Account<'info, Escrow>>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        constraint = initializer_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = initializer_token_account.owner == initializer.key() @ EscrowError::InvalidInitializer,
+    )]
+    pub initializer_token_account: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = initializer,
+        space = 8 + Escrow::LEN,
+        seeds = [b"escrow", initializer.key().as_ref(), external_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub successor: Box<Account<'info, Escrow>>,
+    #[account(
+        init_if_needed,
+        payer = initializer,
+        associated_token::mint = mint,
+        associated_token::authority = successor
+    )]
+    pub successor_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", mint.key().as_ref()],
+        bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct Decline<'info> {
+    pub recipient: Signer<'info>,
+    #[account(
+        mut,
+        constraint = initializer_refund_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = initializer_refund_token_account.owner == escrow_state.initializer @ EscrowError::InvalidInitializer,
+    )]
+    pub initializer_refund_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = escrow_state.recipient == recipient.key() @ EscrowError::InvalidRecipient,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterArbiter<'info> {
+    #[account(mut)]
+    pub registrar: Signer<'info>,
+    pub arbiter: Signer<'info>,
+    #[account(
+        init_if_needed,
+        payer = registrar,
+        space = 8 + ArbiterRegistration::LEN,
+        seeds = [b"arbiter", registrar.key().as_ref(), arbiter.key().as_ref()],
+        bump
+    )]
+    pub registration: Account<'info, ArbiterRegistration>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DeregisterArbiter<'info> {
+    /// Either the registrar or the arbiter.
+    pub authority: Signer<'info>,
+    /// CHECK: Receives the rent; must be the registrar who paid it.
+    #[account(mut, address = registration.registrar)]
+    pub registrar: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        close = registrar,
+        constraint = registration.registrar == authority.key()
+            || registration.arbiter == authority.key() @ EscrowError::InvalidArbiter,
+        seeds = [b"arbiter", registration.registrar.as_ref(), registration.arbiter.as_ref()],
+        bump = registration.bump,
+    )]
+    pub registration: Account<'info, ArbiterRegistration>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimCase<'info> {
+    pub arbiter: Signer<'info>,
+    #[account(
+        seeds = [b"arbiter", escrow_state.arbiter_registrar.as_ref(), arbiter.key().as_ref()],
+        bump = registration.bump,
+    )]
+    pub registration: Account<'info, ArbiterRegistration>,
+    #[account(
+        mut,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptArbiterRole<'info> {
+    pub arbiter: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.arbiter == arbiter.key() @ EscrowError::InvalidArbiter,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct AppointArbiter<'info> {
+    pub initializer: Signer<'info>,
+    pub recipient: Signer<'info>,
+    /// CHECK: Any key may be named; it has to accept before acting.
+    pub new_arbiter: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        constraint = escrow_state.recipient == recipient.key() @ EscrowError::InvalidRecipient,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct SetBackupArbiter<'info> {
+    pub initializer: Signer<'info>,
+    pub recipient: Signer<'info>,
+    /// CHECK: Any key may be named; it signs when taking over.
+    pub backup_arbiter: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        constraint = escrow_state.recipient == recipient.key() @ EscrowError::InvalidRecipient,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct SetAuditor<'info> {
+    pub initializer: Signer<'info>,
+    pub recipient: Signer<'info>,
+    /// CHECK: Any key may be named; it signs when requesting a snapshot.
+    pub auditor: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        constraint = escrow_state.recipient == recipient.key() @ EscrowError::InvalidRecipient,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct ResignArbiter<'info> {
+    pub arbiter: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.arbiter == arbiter.key() @ EscrowError::InvalidArbiter,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct AssumeBackupArbiter<'info> {
+    pub backup_arbiter: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.backup_arbiter == backup_arbiter.key() @ EscrowError::InvalidArbiter,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveByArbiter<'info> {
+    #[account(mut)]
+    pub arbiter: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.arbiter == arbiter.key() @ EscrowError::InvalidArbiter,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    #[account(
+        mut,
+        constraint = recipient_deposit_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = recipient_deposit_token_account.owner == escrow_state.recipient @ EscrowError::InvalidRecipient,
+    )]
+    pub recipient_deposit_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = initializer_refund_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = initializer_refund_token_account.owner == escrow_state.initializer @ EscrowError::InvalidInitializer,
+    )]
+    pub initializer_refund_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveBatch<'info> {
+    pub arbiter: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    amount: u64,
+    timeout: i64,
+    terms_hash: [u8; 32],
+    external_id: u64,
+    invoice_hash: [u8; 32],
+    order_seeded: bool,
+)]
+pub struct Initialize<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    /// CHECK: The recipient is validated in the instruction logic.
+    pub recipient: AccountInfo<'info>,
+    /// CHECK: The arbiter, or none to list the case for registered arbiters
+    /// to `claim_case`; only its address is stored.
+    pub arbiter: Option<UncheckedAccount<'info>>,
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        constraint = initializer_deposit_token_account.amount > 0,
+        constraint = initializer_deposit_token_account.owner == initializer.key()
+    )]
+    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = initializer,
+        space = 8 + Escrow::LEN,
+        seeds = [
+            b"escrow",
+            initializer.key().as_ref(),
+            Escrow::address_seed_for(order_seeded, &recipient.key(), external_id).as_ref(),
+        ],
+        bump
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        init_if_needed,
+        payer = initializer,
+        associated_token::mint = mint,
+        associated_token::authority = escrow_state
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = initializer,
+        space = 8 + MintStats::LEN,
+        seeds = [b"mint_stats", mint.key().as_ref()],
+        bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    /// CHECK: Optional key that must co-sign `withdraw`; only its address is stored.
+    pub withdraw_cosigner: Option<UncheckedAccount<'info>>,
+    /// CHECK: Optional key allowed to refund on the initializer's behalf long
+    /// after the timeout; only its address is stored.
+    pub recovery_authority: Option<UncheckedAccount<'info>>,
+    /// Token account owned by `recovery_authority` that recovered refunds go to.
+    pub recovery_token_account: Option<Account<'info, TokenAccount>>,
+    /// CHECK: Optional account that gets the rent back when the escrow is
+    /// closed, in place of the initializer; only its address is stored.
+    pub rent_collector: Option<UncheckedAccount<'info>>,
+    /// CHECK: The registrar whose arbiters may claim the case, required when
+    /// no arbiter is named; only its address is stored.
+    pub arbiter_registrar: Option<UncheckedAccount<'info>>,
+    /// Optional template whose category parameters the escrow takes.
+    pub template: Option<Account<'info, EscrowTemplate>>,
+    /// The initializer's profile, whose handle is echoed in the creation event.
+    #[account(seeds = [b"party_profile", initializer.key().as_ref()], bump = initializer_profile.bump)]
+    pub initializer_profile: Option<Account<'info, PartyProfile>>,
+}
+
+#[derive(Accounts)]
+pub struct CreateEscrow<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    /// CHECK: The recipient is validated in the instruction logic.
+    pub recipient: AccountInfo<'info>,
+    /// CHECK: The arbiter, or none to list the case for registered arbiters
+    /// to `claim_case`; only its address is stored.
+    pub arbiter: Option<UncheckedAccount<'info>>,
+    pub mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = initializer,
+        space = 8 + Escrow::LEN,
+        seeds = [b"escrow", initializer.key().as_ref(), recipient.key().as_ref()],
+        bump
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        init_if_needed,
+        payer = initializer,
+        associated_token::mint = mint,
+        associated_token::authority = escrow_state
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = initializer,
+        space = 8 + MintStats::LEN,
+        seeds = [b"mint_stats", mint.key().as_ref()],
+        bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    /// CHECK: Optional spl-governance proposal gating `withdraw`; its owner
+    /// and layout are validated in the instruction logic.
+    pub governance_proposal: Option<UncheckedAccount<'info>>,
+    /// CHECK: Optional key that must co-sign `withdraw`; only its address is stored.
+    pub withdraw_cosigner: Option<UncheckedAccount<'info>>,
+    /// CHECK: The spl-governance deployment that must own `governance_proposal`;
+    /// required together with it.
+    #[account(executable)]
+    pub governance_program: Option<UncheckedAccount<'info>>,
+    /// CHECK: Optional account that gets the rent back when the escrow is
+    /// closed, in place of the initializer; only its address is stored.
+    pub rent_collector: Option<UncheckedAccount<'info>>,
+    /// CHECK: The registrar whose arbiters may claim the case, required when
+    /// no arbiter is named; only its address is stored.
+    pub arbiter_registrar: Option<UncheckedAccount<'info>>,
+    /// Optional template whose category parameters the escrow takes.
+    pub template: Option<Account<'info, EscrowTemplate>>,
+    /// The initializer's profile, whose handle is echoed in the creation event.
+    #[account(seeds = [b"party_profile", initializer.key().as_ref()], bump = initializer_profile.bump)]
+    pub initializer_profile: Option<Account<'info, PartyProfile>>,
+}
+
+#[derive(Accounts)]
+pub struct FundEscrow<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = initializer_deposit_token_account.owner == initializer.key()
+    )]
+    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct TopUp<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = initializer_deposit_token_account.owner == initializer.key()
+    )]
+    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Contribute<'info> {
+    #[account(mut)]
+    pub contributor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = contributor_token_account.owner == contributor.key()
+    )]
+    pub contributor_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        init_if_needed,
+        payer = contributor,
+        space = 8 + Contribution::LEN,
+        seeds = [b"contribution", escrow_state.key().as_ref(), contributor.key().as_ref()],
+        bump
+    )]
+    pub contribution: Account<'info, Contribution>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ActivateEscrow<'info> {
+    #[account(
+        mut,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimContribution<'info> {
+    #[account(mut)]
+    pub contributor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = contributor_refund_token_account.owner == contributor.key()
+    )]
+    pub contributor_refund_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"contribution", escrow_state.key().as_ref(), contributor.key().as_ref()],
+        bump = contribution.bump,
+    )]
+    pub contribution: Account<'info, Contribution>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    #[account(mut)]
+    pub recipient: Signer<'info>,
+    #[account(
+        mut,
+        constraint = recipient_deposit_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = recipient_deposit_token_account.owner == escrow_state.recipient @ EscrowError::InvalidRecipient,
+    )]
+    pub recipient_deposit_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = escrow_state.recipient == recipient.key() @ EscrowError::InvalidRecipient,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: Required when the escrow is gated by a governance proposal;
+    /// validated against the stored proposal in the instruction logic.
+    pub governance_proposal: Option<UncheckedAccount<'info>>,
+    /// Required when the escrow names a withdraw co-signer.
+    pub withdraw_cosigner: Option<Signer<'info>>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct EnableHeartbeat<'info> {
+    pub initializer: Signer<'info>,
+    pub recipient: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        constraint = escrow_state.recipient == recipient.key() @ EscrowError::InvalidRecipient,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct EnableSettlementGuard<'info> {
+    pub initializer: Signer<'info>,
+    pub recipient: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        constraint = escrow_state.recipient == recipient.key() @ EscrowError::InvalidRecipient,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct Heartbeat<'info> {
+    /// Either the initializer or the recipient.
+    pub party: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == party.key()
+            || escrow_state.recipient == party.key() @ EscrowError::InvalidParty,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct RaiseDispute<'info> {
+    /// Either the initializer or the recipient.
+    pub party: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == party.key()
+            || escrow_state.recipient == party.key() @ EscrowError::InvalidParty,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    /// The party's profile, whose handle is echoed in `DisputeRaised`.
+    #[account(seeds = [b"party_profile", party.key().as_ref()], bump = party_profile.bump)]
+    pub party_profile: Option<Account<'info, PartyProfile>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawDispute<'info> {
+    /// The party who raised the dispute.
+    pub party: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct ReconcileVault<'info> {
+    #[account(
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct RequestAuditSnapshot<'info> {
+    pub auditor: Signer<'info>,
+    #[account(
+        constraint = escrow_state.auditor == auditor.key() @ EscrowError::InvalidAuditor,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SweepSurplus<'info> {
+    #[account(
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        constraint = initializer_refund_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = initializer_refund_token_account.owner == escrow_state.initializer @ EscrowError::InvalidInitializer,
+    )]
+    pub initializer_refund_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct EmitTimeoutWarning<'info> {
+    #[account(
+        mut,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct PostMessage<'info> {
+    /// The initializer, the recipient or the arbiter.
+    pub sender: Signer<'info>,
+    #[account(
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
 
 #[derive(Accounts)]
-pub struct ResolveByArbiter<'info> {
+pub struct SetHandle<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + PartyProfile::LEN,
+        seeds = [b"party_profile", owner.key().as_ref()],
+        bump
+    )]
+    pub profile: Account<'info, PartyProfile>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClearHandle<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"party_profile", owner.key().as_ref()],
+        bump = profile.bump,
+    )]
+    pub profile: Account<'info, PartyProfile>,
+}
+
+#[derive(Accounts)]
+pub struct MarkDelivered<'info> {
+    pub recipient: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.recipient == recipient.key() @ EscrowError::InvalidRecipient,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterBeneficiary<'info> {
+    pub recipient: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.recipient == recipient.key() @ EscrowError::InvalidRecipient,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    pub beneficiary_token_account: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAsBeneficiary<'info> {
+    pub beneficiary: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.beneficiary_token_account == beneficiary_token_account.key() @ EscrowError::InvalidBeneficiary,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    #[account(
+        mut,
+        constraint = beneficiary_token_account.owner == beneficiary.key() @ EscrowError::InvalidBeneficiary,
+    )]
+    pub beneficiary_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u8)]
+pub struct AddMilestone<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        init,
+        payer = initializer,
+        space = 8 + Milestone::LEN,
+        seeds = [b"milestone", escrow_state.key().as_ref(), index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub milestone: Account<'info, Milestone>,
+    /// Arbiter for this milestone only, accepting the role by signing. The
+    /// escrow's arbiter handles the milestone if omitted.
+    pub arbiter: Option<Signer<'info>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenTrade<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    pub taker_mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = initializer,
+        space = 8 + Trade::LEN,
+        seeds = [b"trade", escrow_state.key().as_ref()],
+        bump
+    )]
+    pub trade: Account<'info, Trade>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Exchange<'info> {
     #[account(mut)]
+    pub recipient: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.recipient == recipient.key() @ EscrowError::InvalidRecipient,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"trade", escrow_state.key().as_ref()],
+        bump = trade.bump,
+    )]
+    pub trade: Account<'info, Trade>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(address = trade.taker_mint @ EscrowError::InvalidMint)]
+    pub taker_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    #[account(
+        mut,
+        constraint = recipient_payment_token_account.owner == recipient.key()
+    )]
+    pub recipient_payment_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = initializer_receive_token_account.mint == trade.taker_mint @ EscrowError::InvalidMint,
+        constraint = initializer_receive_token_account.owner == escrow_state.initializer @ EscrowError::InvalidInitializer,
+    )]
+    pub initializer_receive_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = recipient_deposit_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = recipient_deposit_token_account.owner == escrow_state.recipient @ EscrowError::InvalidRecipient,
+    )]
+    pub recipient_deposit_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseMilestone<'info> {
+    /// The initializer, or the arbiter of a disputed escrow once they have accepted.
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"milestone", escrow_state.key().as_ref(), milestone.index.to_le_bytes().as_ref()],
+        bump = milestone.bump,
+    )]
+    pub milestone: Account<'info, Milestone>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    #[account(
+        mut,
+        constraint = recipient_deposit_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = recipient_deposit_token_account.owner == escrow_state.recipient @ EscrowError::InvalidRecipient,
+    )]
+    pub recipient_deposit_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct RaiseMilestoneDispute<'info> {
+    /// Either the initializer or the recipient.
+    pub party: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == party.key()
+            || escrow_state.recipient == party.key() @ EscrowError::InvalidParty,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"milestone", escrow_state.key().as_ref(), milestone.index.to_le_bytes().as_ref()],
+        bump = milestone.bump,
+    )]
+    pub milestone: Account<'info, Milestone>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveMilestoneDispute<'info> {
+    /// The milestone's arbiter.
+    pub arbiter: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"milestone", escrow_state.key().as_ref(), milestone.index.to_le_bytes().as_ref()],
+        bump = milestone.bump,
+    )]
+    pub milestone: Account<'info, Milestone>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    #[account(
+        mut,
+        constraint = recipient_deposit_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = recipient_deposit_token_account.owner == escrow_state.recipient @ EscrowError::InvalidRecipient,
+    )]
+    pub recipient_deposit_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = initializer_refund_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = initializer_refund_token_account.owner == escrow_state.initializer @ EscrowError::InvalidInitializer,
+    )]
+    pub initializer_refund_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseRetainage<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
+    )]
+    pub escrow_state: Account<'info, Escrow>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    #[account(
+        mut,
+        constraint = recipient_deposit_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = recipient_deposit_token_account.owner == escrow_state.recipient @ EscrowError::InvalidRecipient,
+    )]
+    pub recipient_deposit_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveRetainage<'info> {
     pub arbiter: Signer<'info>,
     #[account(
         mut,
         constraint = escrow_state.arbiter == arbiter.key() @ EscrowError::InvalidArbiter,
-        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.recipient.as_ref()],
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
         bump = escrow_state.escrow_bump,
     )]
     pub escrow_state: Account<'info, Escrow>,
     #[account(
         mut,
-        seeds = [b"vault", escrow_state.key().as_ref()],
-        bump = escrow_state.vault_bump,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
     )]
     pub vault: Account<'info, TokenAccount>,
-    #[account(mut)]
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    #[account(
+        mut,
+        constraint = recipient_deposit_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = recipient_deposit_token_account.owner == escrow_state.recipient @ EscrowError::InvalidRecipient,
+    )]
     pub recipient_deposit_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = initializer_refund_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = initializer_refund_token_account.owner == escrow_state.initializer @ EscrowError::InvalidInitializer,
+    )]
     pub initializer_refund_token_account: Account<'info, TokenAccount>,
     pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
 }
 
+#[derive(Accounts)]
+#[instruction(template_id: u64)]
+pub struct CreateTemplate<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + EscrowTemplate::LEN,
+        seeds = [b"template", authority.key().as_ref(), template_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub template: Account<'info, EscrowTemplate>,
+    pub system_program: Program<'info, System>,
+}
 
 #[derive(Accounts)]
-pub struct Initialize<'info> {
+pub struct UpdateTemplate<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        constraint = template.authority == authority.key() @ EscrowError::InvalidTemplateAuthority,
+        seeds = [b"template", template.authority.as_ref(), template.template_id.to_le_bytes().as_ref()],
+        bump = template.bump,
+    )]
+    pub template: Account<'info, EscrowTemplate>,
+}
+
+#[derive(Accounts)]
+pub struct InitMintStats<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + MintStats::LEN,
+        seeds = [b"mint_stats", mint.key().as_ref()],
+        bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateEscrow<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: Too short to deserialize as `Escrow` until migrated; the
+    /// discriminator is checked in the instruction logic.
+    #[account(mut, owner = crate::ID)]
+    pub escrow_state: UncheckedAccount<'info>,
+    /// The escrow's `b"vault"` token account, only needed for escrows from
+    /// before `Escrow` recorded its mint. Its address is checked in the
+    /// instruction logic.
+    pub legacy_vault: Option<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateVault<'info> {
     #[account(mut)]
-    pub initializer: Signer<'info>,
-    /// CHECK: The recipient is validated in the instruction logic.
-    pub recipient: AccountInfo<'info>,
-    /// CHECK: The arbiter is validated in the instruction logic.
-    pub arbiter: AccountInfo<'info>,
-    pub mint: Account<'info, Mint>,
+    pub payer: Signer<'info>,
+    /// CHECK: Only receives the rent of the legacy vault; checked against the
+    /// escrow's rent collector.
+    #[account(mut, address = escrow_state.rent_destination() @ EscrowError::InvalidRentCollector)]
+    pub rent_collector: UncheckedAccount<'info>,
     #[account(
         mut,
-        constraint = initializer_deposit_token_account.amount > 0,
-        constraint = initializer_deposit_token_account.owner == initializer.key()
-    )]
-    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
-    #[account(
-        init,
-        payer = initializer,
-        space = 8 + Escrow::LEN,
-        seeds = [b"escrow", initializer.key().as_ref(), recipient.key().as_ref()],
-        bump
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
+        bump = escrow_state.escrow_bump,
     )]
     pub escrow_state: Account<'info, Escrow>,
+    #[account(address = escrow_state.mint)]
+    pub mint: Account<'info, Mint>,
     #[account(
-        init,
-        payer = initializer,
+        mut,
         seeds = [b"vault", escrow_state.key().as_ref()],
-        bump,
-        token::mint = mint,
-        token::authority = vault
+        bump = escrow_state.vault_bump,
+    )]
+    pub legacy_vault: Account<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = escrow_state,
     )]
     pub vault: Account<'info, TokenAccount>,
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
 #[derive(Accounts)]
-pub struct Withdraw<'info> {
-    #[account(mut)]
-    pub recipient: Signer<'info>,
-    #[account(mut)]
-    pub recipient_deposit_token_account: Account<'info, TokenAccount>,
+pub struct RecoverRefund<'info> {
+    pub recovery_authority: Signer<'info>,
     #[account(
         mut,
-        constraint = escrow_state.recipient == recipient.key() @ EscrowError::InvalidRecipient,
-        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.recipient.as_ref()],
+        constraint = escrow_state.recovery_authority == recovery_authority.key() @ EscrowError::InvalidRecoveryAccount,
+        constraint = escrow_state.recovery_token_account == recovery_token_account.key() @ EscrowError::InvalidRecoveryAccount,
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
         bump = escrow_state.escrow_bump,
     )]
     pub escrow_state: Account<'info, Escrow>,
     #[account(
         mut,
-        seeds = [b"vault", escrow_state.key().as_ref()],
-        bump = escrow_state.vault_bump,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
     )]
     pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+    #[account(mut)]
+    pub recovery_token_account: Account<'info, TokenAccount>,
     pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
 }
 
 #[derive(Accounts)]
 pub struct Refund<'info> {
     #[account(mut)]
     pub initializer: Signer<'info>,
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = initializer_refund_token_account.mint == escrow_state.mint @ EscrowError::InvalidMint,
+        constraint = initializer_refund_token_account.owner == escrow_state.initializer @ EscrowError::InvalidInitializer,
+    )]
     pub initializer_refund_token_account: Account<'info, TokenAccount>,
     #[account(
         mut,
         constraint = escrow_state.initializer == initializer.key() @ EscrowError::InvalidInitializer,
-        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.recipient.as_ref()],
+        seeds = [b"escrow", escrow_state.initializer.as_ref(), escrow_state.address_seed().as_ref()],
         bump = escrow_state.escrow_bump,
     )]
     pub escrow_state: Account<'info, Escrow>,
     #[account(
         mut,
-        seeds = [b"vault", escrow_state.key().as_ref()],
-        bump = escrow_state.vault_bump,
+        associated_token::mint = escrow_state.mint,
+        associated_token::authority = escrow_state,
     )]
     pub vault: Account<'info, TokenAccount>,
+    #[account(address = escrow_state.mint @ EscrowError::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", escrow_state.mint.as_ref()],
+        bump = mint_stats.bump,
+    )]
+    pub mint_stats: Account<'info, MintStats>,
     pub token_program: Program<'info, Token>,
+    /// CHECK: The instructions sysvar, required when the escrow has its
+    /// settlement guard enabled.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
 }
 
+/// On-chain state of a single escrow.
+///
+/// The field order is part of the public interface: the fields clients filter
+/// on most (`status`, `mint`, `recipient`) come first so they sit at fixed
+/// offsets usable in `getProgramAccounts` memcmp filters. New fields must be
+/// appended after the existing ones and never reordered.
+///
+/// To add a field without migrating existing accounts, carve it out of the
+/// front of `reserved`, shrink `reserved` by the same number of bytes and bump
+/// `CURRENT_VERSION`. Older accounts keep zeroed reserved bytes, so the new
+/// field decodes as its all-zero value there; code that needs to tell the two
+/// apart checks `version`. Only a field that no longer fits may grow the
+/// account, restoring `reserved` to 64 bytes; every account written before
+/// then must go through `migrate_escrow` before it decodes again. That last
+/// happened at version 34, and before it at versions 6, 9, 11, 13, 21, 24
+/// and 30.
 #[account]
-#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Escrow {
-    pub initializer: Pubkey,
+    pub status: EscrowStatus,
+    pub mint: Pubkey,
     pub recipient: Pubkey,
+    pub initializer: Pubkey,
     pub arbiter: Pubkey,
     pub amount: u64,
     pub timeout: i64,
-    pub status: EscrowStatus,
+    /// Bump of the legacy `b"vault"` PDA. Escrows now keep their tokens in
+    /// their associated token account, so this is zero unless the escrow still
+    /// needs `migrate_vault`.
     pub vault_bump: u8,
     pub escrow_bump: u8,
+    /// Hash of the signed off-chain agreement, as supplied at initialization.
+    pub terms_hash: [u8; 32],
+    /// Layout version the account was written with.
+    pub version: u8,
+    /// Timeout duration in seconds, applied when the escrow is funded.
+    pub duration: i64,
+    /// Tokens deposited so far; equals `amount` once the escrow is active.
+    pub funded_amount: u64,
+    /// Deadline for reaching `amount` on escrows created with `create_escrow`.
+    pub funding_deadline: i64,
+    /// Funded through `contribute`; refunds are claimed per contributor.
+    pub crowdfunded: bool,
+    /// Minimum deposit needed to activate the escrow after `funding_deadline`.
+    pub min_goal: u64,
+    /// spl-governance proposal that must succeed before `withdraw`, if any.
+    pub governance_proposal: Pubkey,
+    /// Program owning `governance_proposal`.
+    pub governance_program: Pubkey,
+    /// Whether the arbiter has accepted the role.
+    pub arbiter_accepted: bool,
+    /// Deadline for the arbiter to accept before the parties may replace them.
+    pub arbiter_acceptance_deadline: i64,
+    /// Arbiter taking over if a resigned arbiter is not replaced in time, or
+    /// the default pubkey if none was named.
+    pub backup_arbiter: Pubkey,
+    /// End of the replacement window opened by a resignation, or 0.
+    pub replacement_deadline: i64,
+    /// Share of the withdrawal (in basis points) held back until final sign-off.
+    pub retainage_bps: u16,
+    /// Tokens held back from withdrawals and milestone releases and still in
+    /// the vault.
+    pub retained_amount: u64,
+    /// Duration (in seconds) of the warranty period following withdrawal.
+    pub warranty_period: i64,
+    /// End of the warranty period, after which the arbiter may settle the
+    /// retained share.
+    pub warranty_ends_at: i64,
+    /// Caller-defined reference, such as a purchase order number, or 0.
+    pub external_id: u64,
+    /// Hash of the invoice the escrow settles, or all zeros.
+    pub invoice_hash: [u8; 32],
+    /// Hash of the arbiter's written rationale, set by `resolve_by_arbiter`.
+    pub rationale_hash: [u8; 32],
+    /// Key that must co-sign `withdraw`, or the default pubkey if none.
+    pub withdraw_cosigner: Pubkey,
+    /// Key allowed to refund on the initializer's behalf after
+    /// `RECOVERY_DELAY`, or the default pubkey if none.
+    pub recovery_authority: Pubkey,
+    /// Token account recovered refunds are paid to.
+    pub recovery_token_account: Pubkey,
+    /// Token account registered by the recipient as their fallback; its owner
+    /// may claim in their place.
+    pub beneficiary_token_account: Pubkey,
+    /// Recipient inactivity (in seconds), counted from activation, after which
+    /// the beneficiary may claim.
+    pub beneficiary_window: i64,
+    /// Whether `EscrowExpiringSoon` has been emitted for the current timeout.
+    pub timeout_warning_emitted: bool,
+    /// Seconds from now `heartbeat` pushes the timeout to, or 0 when heartbeat
+    /// mode is off.
+    pub heartbeat_increment: i64,
+    /// Longest the escrow may stay claimable in heartbeat mode.
+    pub heartbeat_max_duration: i64,
+    /// Whether the mint had a freeze authority at creation, meaning the vault
+    /// can be frozen by a third party.
+    pub mint_freezable: bool,
+    /// Whether the escrow address is derived from `external_id` instead of
+    /// the recipient.
+    pub order_seeded: bool,
+    /// Whether releases must run in transactions free of programs outside
+    /// `SETTLEMENT_GUARD_ALLOWLIST`.
+    pub settlement_guard: bool,
+    /// Number of milestones added with `add_milestone`.
+    pub milestone_count: u8,
+    /// Unix timestamp before which the recipient cannot be paid, or 0.
+    pub release_not_before: i64,
+    /// Read-only observer allowed to `request_audit_snapshot`, or the default
+    /// pubkey if none.
+    pub auditor: Pubkey,
+    /// Whether the escrow settles through `exchange` against the terms in
+    /// its `Trade` account.
+    pub trade_mode: bool,
+    /// Escrow this one was renewed from, or the default pubkey.
+    pub previous_escrow: Pubkey,
+    /// Sum of the milestones added and not released yet, which may not
+    /// exceed `amount`.
+    pub milestone_total: u64,
+    /// Part of the deposit already released to the recipient by
+    /// `release_milestone` or `exchange` while the escrow stayed open.
+    pub released_amount: u64,
+    /// Time before the timeout from which `emit_timeout_warning` may be
+    /// called. Zero on escrows from before version 27.
+    pub timeout_warning_window: i64,
+    /// Duration the escrow was created with. Unlike `duration`, `heartbeat`
+    /// never extends it. Zero on escrows from before version 28.
+    pub configured_duration: i64,
+    /// Seconds that were left until the timeout when the escrow was put in
+    /// dispute. The countdown is paused for as long as the dispute is open.
+    pub dispute_time_remaining: i64,
+    /// Party who raised the open dispute, or the default pubkey. Only they may
+    /// `withdraw_dispute`.
+    pub dispute_raised_by: Pubkey,
+    /// Number of milestones in a dispute of their own.
+    pub disputed_milestones: u8,
+    /// Account that gets the rent back when the escrow's accounts are closed,
+    /// or the default pubkey for the initializer.
+    pub rent_collector: Pubkey,
+    /// Time (in seconds) the initializer has to review the work once it is
+    /// marked delivered, or 0 when the escrow has no review window.
+    pub dispute_window: i64,
+    /// When the recipient marked the work delivered, or 0.
+    pub delivered_at: i64,
+    /// Registrar whose arbiters may `claim_case` on an escrow created without
+    /// an arbiter, or the default pubkey.
+    pub arbiter_registrar: Pubkey,
+    /// Qualification bits an arbiter claiming the case must be registered with.
+    pub required_qualifications: u32,
+    /// Highest fee (in basis points) an arbiter claiming the case may charge.
+    pub max_arbiter_fee_bps: u16,
+    /// Fee (in basis points) of the arbiter who claimed the case, or 0.
+    pub arbiter_fee_bps: u16,
+    /// Template the escrow was created from, or the default pubkey.
+    pub template: Pubkey,
+    /// Zeroed space for future fields. Left out of the serde encoding, which
+    /// has no support for arrays this long.
+    #[cfg_attr(feature = "serde", serde(skip, default = "zeroed_reserved"))]
+    pub reserved: [u8; 32],
+}
+
+impl Escrow {
+    pub const CURRENT_VERSION: u8 = 35;
+    pub const RESERVED_LEN: usize = 32;
+    pub const LEN: usize = 1 + 32 + 32 + 32 + 32 + 8 + 8 + 1 + 1 + 32 + 1 + 8 + 8 + 8 + 1 + 8
+        + 32 + 32 + 1 + 8 + 32 + 8 + 2 + 8 + 8 + 8 + 8 + 32 + 32 + 32 + 32 + 32 + 32 + 8 + 1
+        + 8 + 8 + 1 + 1 + 1 + 1 + 8 + 32 + 1 + 32 + 8 + 8 + 8 + 8 + 8 + 32 + 1 + 32 + 8 + 8 + 32 + 4 + 2 + 2
+        + 32 + Self::RESERVED_LEN;
+
+    /// Offsets into the raw account data, including the 8-byte discriminator.
+    pub const STATUS_OFFSET: usize = 8;
+    pub const MINT_OFFSET: usize = Self::STATUS_OFFSET + 1;
+    pub const RECIPIENT_OFFSET: usize = Self::MINT_OFFSET + 32;
+    pub const INITIALIZER_OFFSET: usize = Self::RECIPIENT_OFFSET + 32;
+    pub const ARBITER_OFFSET: usize = Self::INITIALIZER_OFFSET + 32;
 }
 
 impl Escrow {
-    pub const LEN: usize = 32 + 32 + 32 + 8 + 8 + 1 + 1 + 1;
+    /// The last seed of the escrow PDA, after `b"escrow"` and the initializer.
+    pub fn address_seed(&self) -> Vec<u8> {
+        Self::address_seed_for(self.order_seeded, &self.recipient, self.external_id)
+    }
+
+    /// The last seed of an escrow PDA: the recipient, or the little-endian
+    /// `external_id` for escrows initialized with `order_seeded`, which lets
+    /// marketplaces derive the address from their order id alone.
+    pub fn address_seed_for(order_seeded: bool, recipient: &Pubkey, external_id: u64) -> Vec<u8> {
+        if order_seeded {
+            external_id.to_le_bytes().to_vec()
+        } else {
+            recipient.to_bytes().to_vec()
+        }
+    }
+
+    /// Time before the timeout from which `emit_timeout_warning` may be
+    /// called. Escrows from before version 27 did not record their own and
+    /// use `TIMEOUT_WARNING_WINDOW`.
+    pub fn warning_window(&self) -> i64 {
+        if self.timeout_warning_window == 0 {
+            TIMEOUT_WARNING_WINDOW
+        } else {
+            self.timeout_warning_window
+        }
+    }
+
+    /// Account the rent of the escrow's accounts goes to when they are closed:
+    /// the designated rent collector, or else the initializer.
+    pub fn rent_destination(&self) -> Pubkey {
+        if self.rent_collector == Pubkey::default() {
+            self.initializer
+        } else {
+            self.rent_collector
+        }
+    }
+
+    /// Duration the escrow was created with, before any `heartbeat`
+    /// extension. Escrows from before version 28 did not record it and fall
+    /// back to their current `duration`.
+    pub fn base_duration(&self) -> i64 {
+        if self.configured_duration == 0 {
+            self.duration
+        } else {
+            self.configured_duration
+        }
+    }
+
+    /// Fails unless the recipient may be paid as far as the review window is
+    /// concerned: the escrow has no `dispute_window`, or the work was marked
+    /// delivered at least that long ago.
+    fn check_review_over(&self, now: i64) -> Result<()> {
+        if self.dispute_window == 0 {
+            return Ok(());
+        }
+        require!(self.delivered_at > 0, EscrowError::NotDelivered);
+        let review_ends_at = self
+            .delivered_at
+            .checked_add(self.dispute_window)
+            .ok_or(EscrowError::Overflow)?;
+        require!(now >= review_ends_at, EscrowError::ReviewWindowOpen);
+        Ok(())
+    }
+
+    /// Validates and records the terms shared by `initialize` and
+    /// `create_escrow`. The caller sets the status and bumps.
+    #[allow(clippy::too_many_arguments)]
+    fn record_terms(
+        &mut self,
+        mint: Pubkey,
+        initializer: Pubkey,
+        recipient: Pubkey,
+        arbiter: Pubkey,
+        amount: u64,
+        duration: i64,
+        terms_hash: [u8; 32],
+        timeout_warning_window: i64,
+        dispute_window: i64,
+    ) -> Result<()> {
+        require!(amount > 0, EscrowError::InvalidAmount);
+        require!(initializer != recipient, EscrowError::InvalidRecipient);
+        require!(
+            timeout_warning_window > 0 && timeout_warning_window <= duration,
+            EscrowError::InvalidWarningWindow
+        );
+        require!(
+            dispute_window == 0
+                || ((MIN_DISPUTE_WINDOW..=MAX_DISPUTE_WINDOW).contains(&dispute_window)
+                    && dispute_window < duration),
+            EscrowError::InvalidDisputeWindow
+        );
+
+        self.mint = mint;
+        self.initializer = initializer;
+        self.recipient = recipient;
+        self.arbiter = arbiter;
+        self.amount = amount;
+        self.duration = duration;
+        self.configured_duration = duration;
+        self.terms_hash = terms_hash;
+        self.timeout_warning_window = timeout_warning_window;
+        self.dispute_window = dispute_window;
+        self.delivered_at = 0;
+        self.arbiter_accepted = false;
+        self.arbiter_acceptance_deadline = Clock::get()?
+            .unix_timestamp
+            .checked_add(ARBITER_ACCEPTANCE_PERIOD)
+            .ok_or(EscrowError::Overflow)?;
+        self.version = Self::CURRENT_VERSION;
+        Ok(())
+    }
+
+    /// Validates and records how the arbiter is found: escrows naming their
+    /// arbiter take no listing, while the others must name the registrar
+    /// whose arbiters may `claim_case` and a fee cap.
+    fn record_listing(
+        &mut self,
+        named_arbiter: bool,
+        arbiter_registrar: Option<Pubkey>,
+        required_qualifications: u32,
+        max_arbiter_fee_bps: u16,
+    ) -> Result<()> {
+        match arbiter_registrar {
+            None => require!(
+                named_arbiter && required_qualifications == 0 && max_arbiter_fee_bps == 0,
+                EscrowError::InvalidCaseListing
+            ),
+            Some(registrar) => {
+                require!(
+                    !named_arbiter && max_arbiter_fee_bps <= MAX_BPS,
+                    EscrowError::InvalidCaseListing
+                );
+                self.arbiter_registrar = registrar;
+            }
+        }
+        self.required_qualifications = required_qualifications;
+        self.max_arbiter_fee_bps = max_arbiter_fee_bps;
+        Ok(())
+    }
+
+    /// Takes the parameters of `template`, which replace the ones passed at
+    /// creation; those must be left at 0 so a template is never silently
+    /// overridden. The fee cap only applies to escrows listing their case.
+    fn apply_template(&mut self, template: &Account<EscrowTemplate>) -> Result<()> {
+        require!(
+            self.dispute_window == 0
+                && self.retainage_bps == 0
+                && self.warranty_period == 0
+                && self.max_arbiter_fee_bps == 0,
+            EscrowError::TemplateOverridden
+        );
+        require!(
+            template.dispute_window < self.duration,
+            EscrowError::InvalidDisputeWindow
+        );
+
+        self.dispute_window = template.dispute_window;
+        self.retainage_bps = template.retainage_bps;
+        self.warranty_period = template.warranty_period;
+        if self.arbiter_registrar != Pubkey::default() {
+            self.max_arbiter_fee_bps = template.max_arbiter_fee_bps;
+        }
+        self.template = template.key();
+        Ok(())
+    }
+
+    /// Validates a deposit into a `Funding` escrow and books it, activating
+    /// the escrow once the full amount is in. Returns the new funded amount.
+    fn record_deposit(&mut self, amount: u64, depositor: &Pubkey) -> Result<u64> {
+        require!(amount > 0, EscrowError::InvalidAmount);
+        require!(
+            self.status == EscrowStatus::Funding,
+            EscrowError::InvalidState
+        );
+        require!(self.arbiter_accepted, EscrowError::ArbiterNotAccepted);
+        require!(
+            Clock::get()?.unix_timestamp < self.funding_deadline,
+            EscrowError::FundingDeadlinePassed
+        );
+        let funded_amount = self
+            .funded_amount
+            .checked_add(amount)
+            .ok_or(EscrowError::Overflow)?;
+        require!(
+            funded_amount <= self.amount,
+            EscrowError::FundingExceedsTarget
+        );
+
+        self.funded_amount = funded_amount;
+        if funded_amount == self.amount {
+            self.activate(depositor)?;
+        }
+        Ok(funded_amount)
+    }
+
+    /// Whether a `Funding` escrow's funding period ended below `min_goal`,
+    /// making its deposits refundable.
+    fn missed_funding_goal(&self, now: i64) -> bool {
+        now >= self.funding_deadline && self.funded_amount < self.min_goal
+    }
+
+    /// Tokens the vault should hold, given the escrow's status.
+    pub fn expected_vault_balance(&self) -> u64 {
+        match self.status {
+            EscrowStatus::Funding => self.funded_amount,
+            EscrowStatus::Initialized | EscrowStatus::Disputed => {
+                self.amount.saturating_add(self.retained_amount)
+            }
+            EscrowStatus::Withdrawn => self.retained_amount,
+            // Contributors claim their deposits back one at a time.
+            EscrowStatus::Refunded | EscrowStatus::Cancelled if self.crowdfunded => {
+                self.funded_amount
+            }
+            // What was held back from released milestones stays until sign-off.
+            EscrowStatus::Refunded | EscrowStatus::Cancelled => self.retained_amount,
+        }
+    }
+
+    /// Whether the escrow has been paid out or returned, leaving at most the
+    /// retained share in the vault.
+    fn is_settled(&self) -> bool {
+        matches!(
+            self.status,
+            EscrowStatus::Withdrawn | EscrowStatus::Refunded | EscrowStatus::Cancelled
+        )
+    }
+
+    /// Share of a payout of `amount` held back until final sign-off.
+    fn retainage(&self, amount: u64) -> Result<u64> {
+        let retained = (amount as u128)
+            .checked_mul(self.retainage_bps as u128)
+            .ok_or(EscrowError::Overflow)?
+            / MAX_BPS as u128;
+        Ok(retained as u64)
+    }
+
+    /// Whether a resigned arbiter's replacement window has closed and a
+    /// backup arbiter is waiting to take over.
+    fn backup_arbiter_due(&self, now: i64) -> bool {
+        self.replacement_deadline != 0
+            && now >= self.replacement_deadline
+            && self.backup_arbiter != Pubkey::default()
+    }
+
+    /// The role `key` plays in this escrow, if any.
+    fn role_of(&self, key: &Pubkey) -> Option<EscrowRole> {
+        if *key == self.initializer {
+            Some(EscrowRole::Initializer)
+        } else if *key == self.recipient {
+            Some(EscrowRole::Recipient)
+        } else if *key == self.arbiter {
+            Some(EscrowRole::Arbiter)
+        } else {
+            None
+        }
+    }
+
+    /// Moves the escrow to `status`. With the `debug-trace` feature, the
+    /// transition is logged along with the acting key and the amount moved.
+    fn set_status(&mut self, status: EscrowStatus, actor: &Pubkey, amount: u64) {
+        #[cfg(feature = "debug-trace")]
+        msg!(
+            "escrow transition: {:?} -> {:?} by {} amount {}",
+            self.status,
+            status,
+            actor,
+            amount
+        );
+        #[cfg(not(feature = "debug-trace"))]
+        let _ = (actor, amount);
+        self.status = status;
+    }
+
+    /// Starts the timeout and makes the escrow claimable.
+    fn activate(&mut self, actor: &Pubkey) -> Result<()> {
+        self.timeout = Clock::get()?
+            .unix_timestamp
+            .checked_add(self.duration)
+            .ok_or(EscrowError::Overflow)?;
+        self.set_status(EscrowStatus::Initialized, actor, self.amount);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+fn zeroed_reserved() -> [u8; Escrow::RESERVED_LEN] {
+    [0; Escrow::RESERVED_LEN]
+}
+
+impl Default for Escrow {
+    fn default() -> Self {
+        // Every field defaults to its all-zero encoding, which is also what
+        // reserved bytes hold on accounts written before the field existed.
+        Self::deserialize(&mut &[0u8; Self::LEN][..]).unwrap()
+    }
+}
+
+/// The `Escrow` layout the program first shipped with, before the fields were
+/// reordered for memcmp filtering. It does not record the mint, which
+/// `migrate_escrow` reads from the escrow's `b"vault"` token account instead.
+#[derive(AnchorDeserialize)]
+struct LegacyEscrow {
+    initializer: Pubkey,
+    recipient: Pubkey,
+    arbiter: Pubkey,
+    amount: u64,
+    timeout: i64,
+    status: EscrowStatus,
+    vault_bump: u8,
+    escrow_bump: u8,
+}
+
+impl LegacyEscrow {
+    const LEN: usize = 32 + 32 + 32 + 8 + 8 + 1 + 1 + 1;
+
+    /// The same escrow in the current layout. Fields the legacy layout did not
+    /// have keep their all-zero value, as in any other migration.
+    fn upgrade(self, mint: Pubkey) -> Escrow {
+        Escrow {
+            status: self.status,
+            mint,
+            recipient: self.recipient,
+            initializer: self.initializer,
+            arbiter: self.arbiter,
+            amount: self.amount,
+            timeout: self.timeout,
+            vault_bump: self.vault_bump,
+            escrow_bump: self.escrow_bump,
+            funded_amount: self.amount,
+            ..Escrow::default()
+        }
+    }
+}
+
+/// Receipt of a single contributor's deposits into a crowdfunded escrow.
+///
+/// Receipts outlive the escrow so they can serve as proof of contribution.
+#[account]
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Contribution {
+    pub escrow: Pubkey,
+    pub contributor: Pubkey,
+    pub amount: u64,
+    pub first_contributed_at: i64,
+    pub last_contributed_at: i64,
+    /// Set once the contribution has been returned by `claim_contribution`.
+    pub claimed: bool,
+    pub bump: u8,
+}
+
+impl Contribution {
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 1 + 1;
+}
+
+/// One part of an escrow's amount, at `[b"milestone", escrow, index]`.
+///
+/// Like contribution receipts, milestones are kept after release.
+#[account]
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Milestone {
+    pub escrow: Pubkey,
+    pub index: u8,
+    pub amount: u64,
+    /// When the milestone is due, for the parties' reference.
+    pub deadline: i64,
+    pub released: bool,
+    pub bump: u8,
+    /// Arbiter for disputes over this milestone only, or the default pubkey
+    /// to leave them to the escrow's arbiter.
+    pub arbiter: Pubkey,
+    /// Whether the milestone is in a dispute of its own.
+    pub disputed: bool,
+}
+
+impl Milestone {
+    pub const LEN: usize = 32 + 1 + 8 + 8 + 1 + 1 + 32 + 1;
+
+    /// The key that rules on disputes over this milestone.
+    pub fn arbiter_for(&self, escrow: &Escrow) -> Pubkey {
+        if self.arbiter == Pubkey::default() {
+            escrow.arbiter
+        } else {
+            self.arbiter
+        }
+    }
+}
+
+/// Terms of an OTC trade escrow, at `[b"trade", escrow]`: what the recipient
+/// pays the initializer in `exchange` for the deposit.
+#[account]
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trade {
+    pub escrow: Pubkey,
+    pub taker_mint: Pubkey,
+    /// Payment still owed for the unfilled part of the deposit.
+    pub taker_amount: u64,
+    pub bump: u8,
+    /// Part of the deposit already bought through `exchange`.
+    pub filled_amount: u64,
+    /// Deposit and payment when the trade was opened, which set the price
+    /// every fill is charged at.
+    pub original_amount: u64,
+    pub original_taker_amount: u64,
+}
+
+impl Trade {
+    pub const LEN: usize = 32 + 32 + 8 + 1 + 8 + 8 + 8;
+}
+
+/// Running totals over every escrow of one mint, at `[b"mint_stats", mint]`.
+///
+/// The totals are informational and only count what happened after the
+/// account was created, so they saturate instead of failing a transfer.
+#[account]
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MintStats {
+    pub mint: Pubkey,
+    /// Escrows that are funding or funded and not settled yet.
+    pub active_escrows: u64,
+    /// Tokens currently held in the vaults of this mint's escrows.
+    pub total_locked: u64,
+    /// Tokens ever deposited into this mint's escrows.
+    pub lifetime_volume: u64,
+    pub bump: u8,
+}
+
+impl MintStats {
+    pub const LEN: usize = 32 + 8 + 8 + 8 + 1;
+
+    fn record_opened(&mut self, mint: Pubkey, bump: u8) {
+        self.mint = mint;
+        self.bump = bump;
+        self.active_escrows = self.active_escrows.saturating_add(1);
+    }
+
+    fn record_settled(&mut self) {
+        self.active_escrows = self.active_escrows.saturating_sub(1);
+    }
+
+    fn record_deposit(&mut self, amount: u64) {
+        self.total_locked = self.total_locked.saturating_add(amount);
+        self.lifetime_volume = self.lifetime_volume.saturating_add(amount);
+    }
+
+    fn record_release(&mut self, amount: u64) {
+        self.total_locked = self.total_locked.saturating_sub(amount);
+    }
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+/// An arbiter's registration with a registrar, at
+/// `[b"arbiter", registrar, arbiter]`.
+#[account]
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArbiterRegistration {
+    pub registrar: Pubkey,
+    pub arbiter: Pubkey,
+    /// Qualification bits attested by the registrar.
+    pub qualifications: u32,
+    /// Fee (in basis points) the arbiter charges.
+    pub fee_bps: u16,
+    pub bump: u8,
+}
+
+impl ArbiterRegistration {
+    pub const LEN: usize = 32 + 32 + 4 + 2 + 1;
+}
+
+/// A party's display handle, at `[b"party_profile", owner]`.
+#[account]
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PartyProfile {
+    pub owner: Pubkey,
+    pub handle: String,
+    pub bump: u8,
+}
+
+impl PartyProfile {
+    pub const LEN: usize = 32 + 4 + MAX_HANDLE_LEN + 1;
+}
+
+/// The handle in `profile`, or an empty string for parties without one.
+fn handle_of(profile: &Option<Account<PartyProfile>>) -> String {
+    profile
+        .as_ref()
+        .map(|profile| profile.handle.clone())
+        .unwrap_or_default()
+}
+
+/// Parameters shared by every escrow of a category, at
+/// `[b"template", authority, template_id.to_le_bytes()]`.
+#[account]
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowTemplate {
+    /// Key allowed to `update_template`.
+    pub authority: Pubkey,
+    pub template_id: u64,
+    pub category: EscrowCategory,
+    /// Review window given to escrows created from the template, or 0.
+    pub dispute_window: i64,
+    pub retainage_bps: u16,
+    pub warranty_period: i64,
+    /// Fee cap for arbiters claiming the cases of escrows created from the
+    /// template.
+    pub max_arbiter_fee_bps: u16,
+    pub bump: u8,
+}
+
+impl EscrowTemplate {
+    pub const LEN: usize = 32 + 8 + 1 + 8 + 2 + 8 + 2 + 1;
+
+    fn record_parameters(
+        &mut self,
+        category: EscrowCategory,
+        dispute_window: i64,
+        retainage_bps: u16,
+        warranty_period: i64,
+        max_arbiter_fee_bps: u16,
+    ) -> Result<()> {
+        require!(
+            dispute_window == 0
+                || (MIN_DISPUTE_WINDOW..=MAX_DISPUTE_WINDOW).contains(&dispute_window),
+            EscrowError::InvalidDisputeWindow
+        );
+        require!(
+            retainage_bps <= MAX_BPS && warranty_period >= 0,
+            EscrowError::InvalidRetainage
+        );
+        require!(max_arbiter_fee_bps <= MAX_BPS, EscrowError::InvalidArbiterFee);
+
+        self.category = category;
+        self.dispute_window = dispute_window;
+        self.retainage_bps = retainage_bps;
+        self.warranty_period = warranty_period;
+        self.max_arbiter_fee_bps = max_arbiter_fee_bps;
+        Ok(())
+    }
+}
+
+/// Checks that the arbiter may resolve the escrow now, paying the recipient
+/// if `pays_recipient`.
+fn require_resolvable(
+    accounts: &ResolveByArbiter,
+    pays_recipient: bool,
+    rationale_hash: [u8; 32],
+) -> Result<()> {
+    let escrow_state = &accounts.escrow_state;
+
+    require!(
+        escrow_state.status == EscrowStatus::Disputed,
+        EscrowError::NotDisputed
+    );
+    require!(rationale_hash != [0; 32], EscrowError::MissingRationale);
+    require!(
+        escrow_state.arbiter_accepted,
+        EscrowError::ArbiterNotAccepted
+    );
+    if pays_recipient {
+        require!(!escrow_state.trade_mode, EscrowError::TradeRequiresExchange);
+        require!(
+            Clock::get()?.unix_timestamp >= escrow_state.release_not_before,
+            EscrowError::ReleaseNotYetAllowed
+        );
+    }
+    check_settlement_guard(escrow_state, accounts.instructions_sysvar.as_ref())?;
+    Ok(())
+}
+
+/// Settles an escrow as the arbiter decided: pays the recipient, refunds the
+/// initializer, or leaves a crowdfunded escrow's refunds to its contributors.
+fn resolve_escrow(
+    accounts: &mut ResolveByArbiter,
+    release_to_recipient: bool,
+    rationale_hash: [u8; 32],
+) -> Result<()> {
+    require_resolvable(accounts, release_to_recipient, rationale_hash)?;
+    let escrow_state = &mut accounts.escrow_state;
+
+    let address_seed = escrow_state.address_seed();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"escrow".as_ref(),
+        escrow_state.initializer.as_ref(),
+        address_seed.as_ref(),
+        &[escrow_state.escrow_bump],
+    ]];
+
+    if release_to_recipient {
+        // Transfer to recipient
+        let cpi_accounts = TransferChecked {
+            from: accounts.vault.to_account_info(),
+            mint: accounts.mint.to_account_info(),
+            to: accounts.recipient_deposit_token_account.to_account_info(),
+            authority: escrow_state.to_account_info(),
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        let cpi_ctx =
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer_checked(cpi_ctx, escrow_state.amount, accounts.mint.decimals)?;
+        let amount = escrow_state.amount;
+        escrow_state.set_status(EscrowStatus::Withdrawn, accounts.arbiter.key, amount);
+        accounts.mint_stats.record_release(amount);
+    } else if !escrow_state.crowdfunded {
+        // Refund to initializer
+        let cpi_accounts = TransferChecked {
+            from: accounts.vault.to_account_info(),
+            mint: accounts.mint.to_account_info(),
+            to: accounts.initializer_refund_token_account.to_account_info(),
+            authority: escrow_state.to_account_info(),
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        let cpi_ctx =
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer_checked(cpi_ctx, escrow_state.amount, accounts.mint.decimals)?;
+        let amount = escrow_state.amount;
+        escrow_state.set_status(EscrowStatus::Refunded, accounts.arbiter.key, amount);
+        accounts.mint_stats.record_release(amount);
+    } else {
+        // Contributors claim their own share back.
+        escrow_state.set_status(EscrowStatus::Refunded, accounts.arbiter.key, 0);
+    }
+    accounts.mint_stats.record_settled();
+    escrow_state.rationale_hash = rationale_hash;
+
+    emit!(EscrowResolved {
+        escrow: escrow_state.key(),
+        arbiter: *accounts.arbiter.key,
+        release_to_recipient,
+        external_id: escrow_state.external_id,
+        rationale_hash,
+        auditor: escrow_state.auditor,
+        template: escrow_state.template,
+    });
+
+    Ok(())
+}
+
+/// Enforces the settlement guard of an escrow that has it enabled. Every
+/// instruction that pays tokens out of the escrow calls this before the
+/// transfer.
+fn check_settlement_guard(
+    escrow_state: &Escrow,
+    instructions_sysvar: Option<&UncheckedAccount>,
+) -> Result<()> {
+    if escrow_state.settlement_guard {
+        let instructions = instructions_sysvar.ok_or(EscrowError::MissingInstructionsSysvar)?;
+        require_isolated_transaction(instructions)?;
+    }
+    Ok(())
+}
+
+/// Fails unless every top-level instruction of the transaction targets this
+/// program or a program in `SETTLEMENT_GUARD_ALLOWLIST`.
+///
+/// Only top-level instructions are visible in the instructions sysvar. A
+/// program invoked through CPI by an allowlisted program, or by this one, is
+/// not inspected, so the allowlist must only name programs that cannot be
+/// made to call arbitrary code.
+fn require_isolated_transaction(instructions: &AccountInfo) -> Result<()> {
+    let mut index = 0;
+    while let Ok(instruction) = load_instruction_at_checked(index, instructions) {
+        require!(
+            instruction.program_id == crate::ID
+                || SETTLEMENT_GUARD_ALLOWLIST.contains(&instruction.program_id),
+            EscrowError::SettlementNotIsolated
+        );
+        index += 1;
+    }
+    Ok(())
+}
+
+/// `GovernanceAccountType` discriminants of spl-governance proposal accounts.
+pub const GOVERNANCE_PROPOSAL_V1: u8 = 5;
+pub const GOVERNANCE_PROPOSAL_V2: u8 = 14;
+/// `ProposalState` discriminants that count as an approved proposal.
+pub const PROPOSAL_STATE_SUCCEEDED: u8 = 3;
+pub const PROPOSAL_STATE_EXECUTING: u8 = 4;
+pub const PROPOSAL_STATE_COMPLETED: u8 = 5;
+
+/// Reads the `ProposalState` discriminant of an spl-governance proposal
+/// owned by `governance_program`.
+///
+/// `ProposalV1` and `ProposalV2` both start with the account type, the
+/// governance and governing token mint keys, and then the proposal state.
+fn governance_proposal_state(proposal: &AccountInfo, governance_program: &Pubkey) -> Result<u8> {
+    const STATE_OFFSET: usize = 1 + 32 + 32;
+    require_keys_eq!(*proposal.owner, *governance_program, EscrowError::InvalidProposal);
+    let data = proposal.try_borrow_data()?;
+    require!(
+        data.len() > STATE_OFFSET
+            && matches!(data[0], GOVERNANCE_PROPOSAL_V1 | GOVERNANCE_PROPOSAL_V2),
+        EscrowError::InvalidProposal
+    );
+    Ok(data[STATE_OFFSET])
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EscrowStatus {
+    #[default]
     Initialized,
     Withdrawn,
     Refunded,
     Cancelled,
+    /// Created with `create_escrow` and not activated yet.
+    Funding,
+    /// Frozen by `raise_dispute` until the arbiter resolves it.
+    Disputed,
+}
+
+/// What an escrow template is for, so analytics can segment escrows by it.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EscrowCategory {
+    #[default]
+    Goods,
+    Services,
+    Digital,
+}
+
+/// One arbiter decision in a `resolve_batch` call.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BatchResolution {
+    pub escrow: Pubkey,
+    pub release_to_recipient: bool,
+    pub rationale_hash: [u8; 32],
 }
 
-impl Default for EscrowStatus {
-    fn default() -> Self {
-        Self::Initialized
-    }
+/// A party to an escrow, as named in `post_message`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EscrowRole {
+    Initializer,
+    Recipient,
+    Arbiter,
 }
 
 #[error_code]
@@ -435,40 +5299,862 @@ pub enum EscrowError {
     Overflow,
     #[msg("Invalid bump seed.")]
     InvalidBump,
+    #[msg("The funding period must be greater than zero.")]
+    InvalidFundingPeriod,
+    #[msg("The funding deadline has passed.")]
+    FundingDeadlinePassed,
+    #[msg("The deposit would exceed the escrow amount.")]
+    FundingExceedsTarget,
+    #[msg("This escrow does not accept contributions.")]
+    ContributionsNotEnabled,
+    #[msg("This escrow can only be funded through contributions.")]
+    ContributionsOnly,
+    #[msg("Contributions cannot be claimed back from this escrow yet.")]
+    ClaimNotAllowed,
+    #[msg("This contribution has already been claimed.")]
+    AlreadyClaimed,
+    #[msg("The funding goal must be greater than zero and at most the escrow amount.")]
+    InvalidFundingGoal,
+    #[msg("The funding period has not ended yet.")]
+    FundingPeriodActive,
+    #[msg("The funding goal was not reached.")]
+    FundingGoalNotReached,
+    #[msg("The governance proposal account is not valid for this escrow.")]
+    InvalidProposal,
+    #[msg("The governance proposal has not been approved.")]
+    ProposalNotApproved,
+    #[msg("The arbiter has not accepted the role.")]
+    ArbiterNotAccepted,
+    #[msg("The arbiter has already accepted the role.")]
+    ArbiterAlreadyAccepted,
+    #[msg("The arbiter acceptance deadline has passed.")]
+    ArbiterAcceptanceExpired,
+    #[msg("The current arbiter appointment has not lapsed.")]
+    ArbiterAppointmentActive,
+    #[msg("The replacement window has closed; the backup arbiter takes over.")]
+    ReplacementWindowClosed,
+    #[msg("The backup arbiter cannot take over yet.")]
+    ReplacementWindowOpen,
+    #[msg("The retainage must be at most 10000 bps with a non-negative warranty period.")]
+    InvalidRetainage,
+    #[msg("The escrow holds no retained tokens.")]
+    NoRetainage,
+    #[msg("The warranty period has not ended yet.")]
+    WarrantyPeriodActive,
+    #[msg("The escrow account already has the current size.")]
+    AlreadyMigrated,
+    #[msg("A resolution must include the hash of its rationale.")]
+    MissingRationale,
+    #[msg("The withdraw co-signer did not sign.")]
+    MissingCosigner,
+    #[msg("The recovery key and its token account do not match.")]
+    InvalidRecoveryAccount,
+    #[msg("The beneficiary registration or claim is invalid.")]
+    InvalidBeneficiary,
+    #[msg("The recipient's inactivity window has not passed yet.")]
+    RecipientStillActive,
+    #[msg("The timeout warning has already been emitted.")]
+    TimeoutWarningEmitted,
+    #[msg("The escrow is not close enough to its timeout.")]
+    TimeoutNotNear,
+    #[msg("Heartbeat mode is off or its parameters are invalid.")]
+    InvalidHeartbeat,
+    #[msg("The timeout cannot be extended any further.")]
+    HeartbeatLimitReached,
+    #[msg("The signer is neither the initializer nor the recipient.")]
+    InvalidParty,
+    #[msg("The escrow vault is already its associated token account.")]
+    VaultAlreadyMigrated,
+    #[msg("A message must include the hash of its content.")]
+    EmptyMessage,
+    #[msg("The signer is not a party to this escrow.")]
+    NotEscrowParty,
+    #[msg("The escrow's settlement guard requires the instructions sysvar.")]
+    MissingInstructionsSysvar,
+    #[msg("The transaction invokes a program the settlement guard does not allow.")]
+    SettlementNotIsolated,
+    #[msg("The token account does not hold the escrow's mint.")]
+    InvalidMint,
+    #[msg("Crowdfunded escrows stay open for their contribution receipts.")]
+    CrowdfundedEscrow,
+    #[msg("The vault still holds tokens.")]
+    VaultNotEmpty,
+    #[msg("The vault holds no more than the escrow accounts for.")]
+    NoSurplus,
+    #[msg("The milestone is out of order, empty, exceeds the escrow amount, or the escrow is crowdfunded.")]
+    InvalidMilestone,
+    #[msg("The milestone has already been released.")]
+    MilestoneReleased,
+    #[msg("Escrows with milestones or a trade stay open for those accounts.")]
+    MilestonesOutstanding,
+    #[msg("The release date cannot be negative.")]
+    InvalidReleaseDate,
+    #[msg("The funds cannot be released before the escrow's release date.")]
+    ReleaseNotYetAllowed,
+    #[msg("The batch needs one resolution per escrow and a full account group for each.")]
+    InvalidBatch,
+    #[msg("The auditor is not valid for this escrow.")]
+    InvalidAuditor,
+    #[msg("Trades need a positive price on an escrow that pays out in one go.")]
+    InvalidTrade,
+    #[msg("Trade escrows are settled with `exchange`.")]
+    TradeRequiresExchange,
+    #[msg("The recipient's share must be at most 10000 bps.")]
+    InvalidSplit,
+    #[msg("Crowdfunded, milestone and trade escrows cannot be renewed.")]
+    NotRenewable,
+    #[msg("The arbiter can only resolve escrows in dispute.")]
+    NotDisputed,
+    #[msg("The mint has a freeze authority.")]
+    FreezableMint,
+    #[msg("Part of the escrow has already been released to the recipient.")]
+    PartiallyReleased,
+    #[msg("An arbiter cannot resign from a dispute without a backup arbiter.")]
+    BackupArbiterRequired,
+    #[msg("The escrow's legacy vault is missing or wrong.")]
+    InvalidLegacyVault,
+    #[msg("The timeout warning window must be positive and at most the escrow's duration.")]
+    InvalidWarningWindow,
+    #[msg("The deposit does not cover the escrow's milestones, or a trade is open.")]
+    ActivationBlocked,
+    #[msg("Only the party who raised the dispute can withdraw it.")]
+    NotDisputeRaiser,
+    #[msg("A milestone of the escrow is in dispute.")]
+    MilestoneDisputeOpen,
+    #[msg("The account is not the escrow's rent collector.")]
+    InvalidRentCollector,
+    #[msg("The dispute window must be 0 or within the program's bounds, and shorter than the escrow's duration.")]
+    InvalidDisputeWindow,
+    #[msg("The escrow has no dispute window.")]
+    NoDisputeWindow,
+    #[msg("The work has already been marked delivered.")]
+    AlreadyDelivered,
+    #[msg("The recipient has not marked the work delivered.")]
+    NotDelivered,
+    #[msg("The initializer's review window is still open.")]
+    ReviewWindowOpen,
+    #[msg("An escrow must either name its arbiter or list the case with a registrar and a fee cap of at most 100%.")]
+    InvalidCaseListing,
+    #[msg("The escrow is not listed for arbiters to claim.")]
+    CaseNotListed,
+    #[msg("The arbiter lacks a qualification the case requires.")]
+    ArbiterNotQualified,
+    #[msg("The arbiter's fee is above 100% or the case's fee cap.")]
+    InvalidArbiterFee,
+    #[msg("Only the template's authority can update it.")]
+    InvalidTemplateAuthority,
+    #[msg("Parameters set by the template must be passed as 0.")]
+    TemplateOverridden,
+    #[msg("Handles must be 1 to 32 bytes of ASCII letters, digits, '-', '_' and '.'.")]
+    InvalidHandle,
 }
 
 #[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EscrowInitialized {
     pub escrow: Pubkey,
     pub initializer: Pubkey,
     pub recipient: Pubkey,
     pub arbiter: Pubkey,
     pub amount: u64,
+    pub terms_hash: [u8; 32],
+    pub external_id: u64,
+    pub invoice_hash: [u8; 32],
+    pub withdraw_cosigner: Pubkey,
+    pub mint_freezable: bool,
+    pub release_not_before: i64,
+    pub timeout_warning_window: i64,
+    pub dispute_window: i64,
+    /// Registrar whose arbiters may `claim_case`, or the default pubkey when
+    /// the arbiter is named.
+    pub arbiter_registrar: Pubkey,
+    pub required_qualifications: u32,
+    pub max_arbiter_fee_bps: u16,
+    /// Template the escrow was created from, or the default pubkey.
+    pub template: Pubkey,
+    /// The initializer's handle, or empty.
+    pub initializer_handle: String,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowCreated {
+    pub escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub recipient: Pubkey,
+    pub arbiter: Pubkey,
+    pub amount: u64,
+    pub terms_hash: [u8; 32],
+    pub funding_deadline: i64,
+    pub crowdfunded: bool,
+    pub min_goal: u64,
+    pub governance_proposal: Pubkey,
+    pub retainage_bps: u16,
+    pub external_id: u64,
+    pub invoice_hash: [u8; 32],
+    pub withdraw_cosigner: Pubkey,
+    pub mint_freezable: bool,
+    pub release_not_before: i64,
+    pub timeout_warning_window: i64,
+    pub dispute_window: i64,
+    /// Registrar whose arbiters may `claim_case`, or the default pubkey when
+    /// the arbiter is named.
+    pub arbiter_registrar: Pubkey,
+    pub required_qualifications: u32,
+    pub max_arbiter_fee_bps: u16,
+    /// Template the escrow was created from, or the default pubkey.
+    pub template: Pubkey,
+    /// The initializer's handle, or empty.
+    pub initializer_handle: String,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowFunded {
+    pub escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub amount: u64,
+    pub funded_amount: u64,
+    /// Zero until the deposit that completes funding.
+    pub timeout: i64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowToppedUp {
+    pub escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub amount: u64,
+    /// Escrow amount after the top-up.
+    pub total_amount: u64,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowContributed {
+    pub escrow: Pubkey,
+    pub contributor: Pubkey,
+    pub contribution: Pubkey,
+    pub amount: u64,
+    pub total_contributed: u64,
+    pub funded_amount: u64,
+    /// Zero until the contribution that completes funding.
+    pub timeout: i64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowActivated {
+    pub escrow: Pubkey,
+    pub amount: u64,
+    pub timeout: i64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContributionClaimed {
+    pub escrow: Pubkey,
+    pub contributor: Pubkey,
+    pub contribution: Pubkey,
+    pub amount: u64,
 }
 
 #[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EscrowWithdrawn {
     pub escrow: Pubkey,
     pub recipient: Pubkey,
     pub amount: u64,
+    pub retained_amount: u64,
+    pub external_id: u64,
+    /// The escrow's auditor, or the default pubkey if it has none.
+    pub auditor: Pubkey,
+    /// Template the escrow was created from, or the default pubkey.
+    pub template: Pubkey,
 }
 
 #[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EscrowRefunded {
     pub escrow: Pubkey,
     pub initializer: Pubkey,
     pub amount: u64,
+    pub external_id: u64,
+    /// The escrow's auditor, or the default pubkey if it has none.
+    pub auditor: Pubkey,
+    /// Template the escrow was created from, or the default pubkey.
+    pub template: Pubkey,
 }
 
 #[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EscrowCancelled {
     pub escrow: Pubkey,
     pub initializer: Pubkey,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowClosed {
+    pub escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub external_id: u64,
+    /// Account the rent of the escrow and its vault went to.
+    pub rent_collector: Pubkey,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowDelivered {
+    pub escrow: Pubkey,
+    pub recipient: Pubkey,
+    /// End of the initializer's review window.
+    pub review_ends_at: i64,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisputeRaised {
+    pub escrow: Pubkey,
+    pub raised_by: Pubkey,
+    /// Caller-defined code for what the dispute is about.
+    pub reason_code: u8,
+    pub external_id: u64,
+    /// Seconds that were left until the timeout, paused for the dispute.
+    pub time_remaining: i64,
+    /// The raiser's handle, or empty.
+    pub raised_by_handle: String,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisputeWithdrawn {
+    pub escrow: Pubkey,
+    pub withdrawn_by: Pubkey,
+    /// The resumed timeout.
+    pub timeout: i64,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowDeclined {
+    pub escrow: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+    /// Caller-defined code for why the recipient declined.
+    pub reason_code: u8,
+    pub external_id: u64,
 }
 
 #[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EscrowResolved {
     pub escrow: Pubkey,
     pub arbiter: Pubkey,
     pub release_to_recipient: bool,
+    pub external_id: u64,
+    pub rationale_hash: [u8; 32],
+    /// The escrow's auditor, or the default pubkey if it has none.
+    pub auditor: Pubkey,
+    /// Template the escrow was created from, or the default pubkey.
+    pub template: Pubkey,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowRenewed {
+    pub escrow: Pubkey,
+    pub previous_escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub amount: u64,
+    /// Part of the amount carried over from the previous escrow's vault.
+    pub carried: u64,
+    pub timeout: i64,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowSplit {
+    pub escrow: Pubkey,
+    pub arbiter: Pubkey,
+    pub bps_to_recipient: u16,
+    pub recipient_amount: u64,
+    pub initializer_amount: u64,
+    pub external_id: u64,
+    pub rationale_hash: [u8; 32],
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HandleSet {
+    pub owner: Pubkey,
+    pub handle: String,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HandleCleared {
+    pub owner: Pubkey,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TemplateUpdated {
+    pub template: Pubkey,
+    pub authority: Pubkey,
+    pub category: EscrowCategory,
+    pub dispute_window: i64,
+    pub retainage_bps: u16,
+    pub warranty_period: i64,
+    pub max_arbiter_fee_bps: u16,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArbiterRegistered {
+    pub registrar: Pubkey,
+    pub arbiter: Pubkey,
+    pub qualifications: u32,
+    pub fee_bps: u16,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArbiterDeregistered {
+    pub registrar: Pubkey,
+    pub arbiter: Pubkey,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CaseClaimed {
+    pub escrow: Pubkey,
+    pub arbiter: Pubkey,
+    pub fee_bps: u16,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArbiterAccepted {
+    pub escrow: Pubkey,
+    pub arbiter: Pubkey,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArbiterAppointed {
+    pub escrow: Pubkey,
+    pub previous_arbiter: Pubkey,
+    pub arbiter: Pubkey,
+    pub acceptance_deadline: i64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BackupArbiterSet {
+    pub escrow: Pubkey,
+    pub backup_arbiter: Pubkey,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditorSet {
+    pub escrow: Pubkey,
+    pub auditor: Pubkey,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditSnapshot {
+    pub escrow: Pubkey,
+    pub auditor: Pubkey,
+    pub status: EscrowStatus,
+    pub mint: Pubkey,
+    pub initializer: Pubkey,
+    pub recipient: Pubkey,
+    pub arbiter: Pubkey,
+    pub amount: u64,
+    pub funded_amount: u64,
+    pub retained_amount: u64,
+    /// Vault balance implied by the escrow's state.
+    pub expected_vault_balance: u64,
+    pub vault_balance: u64,
+    pub timeout: i64,
+    pub external_id: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArbiterResigned {
+    pub escrow: Pubkey,
+    pub arbiter: Pubkey,
+    pub backup_arbiter: Pubkey,
+    pub replacement_deadline: i64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BackupArbiterAssumed {
+    pub escrow: Pubkey,
+    pub previous_arbiter: Pubkey,
+    pub arbiter: Pubkey,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MilestoneAdded {
+    pub escrow: Pubkey,
+    pub index: u8,
+    pub amount: u64,
+    pub deadline: i64,
+    /// Dedicated arbiter of the milestone, or the default pubkey.
+    pub arbiter: Pubkey,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MilestoneReleased {
+    pub escrow: Pubkey,
+    pub index: u8,
+    pub amount: u64,
+    pub retained_amount: u64,
+    pub released_by: Pubkey,
+    /// Escrow amount left after this release.
+    pub remaining: u64,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MilestoneDisputed {
+    pub escrow: Pubkey,
+    pub index: u8,
+    pub raised_by: Pubkey,
+    /// The arbiter who will rule on the milestone.
+    pub arbiter: Pubkey,
+    /// Caller-defined code for what the dispute is about.
+    pub reason_code: u8,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MilestoneDisputeResolved {
+    pub escrow: Pubkey,
+    pub index: u8,
+    pub arbiter: Pubkey,
+    pub release_to_recipient: bool,
+    /// Tokens paid out, net of retainage.
+    pub amount: u64,
+    pub retained_amount: u64,
+    /// Escrow amount left after this resolution.
+    pub remaining: u64,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TradeOpened {
+    pub escrow: Pubkey,
+    pub amount: u64,
+    pub taker_mint: Pubkey,
+    pub taker_amount: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowExchanged {
+    pub escrow: Pubkey,
+    pub recipient: Pubkey,
+    /// Part of the deposit bought in this fill.
+    pub amount: u64,
+    pub taker_mint: Pubkey,
+    /// Payment made for it.
+    pub taker_amount: u64,
+    /// Deposit left unfilled.
+    pub remaining: u64,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetainageReleased {
+    pub escrow: Pubkey,
+    pub amount: u64,
+    pub released_to_recipient: bool,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowRecovered {
+    pub escrow: Pubkey,
+    pub recovery_authority: Pubkey,
+    pub amount: u64,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BeneficiaryRegistered {
+    pub escrow: Pubkey,
+    pub recipient: Pubkey,
+    pub beneficiary: Pubkey,
+    pub beneficiary_token_account: Pubkey,
+    pub window: i64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BeneficiaryClaimed {
+    pub escrow: Pubkey,
+    pub beneficiary: Pubkey,
+    pub amount: u64,
+    pub retained_amount: u64,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowExpiringSoon {
+    pub escrow: Pubkey,
+    pub timeout: i64,
+    pub external_id: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeartbeatEnabled {
+    pub escrow: Pubkey,
+    pub increment: i64,
+    pub max_duration: i64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowExtended {
+    pub escrow: Pubkey,
+    pub party: Pubkey,
+    pub timeout: i64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SettlementGuardEnabled {
+    pub escrow: Pubkey,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VaultDiscrepancy {
+    pub escrow: Pubkey,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SurplusSwept {
+    pub escrow: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VaultMigrated {
+    pub escrow: Pubkey,
+    pub vault: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MessagePosted {
+    pub escrow: Pubkey,
+    pub sender: Pubkey,
+    pub sender_role: EscrowRole,
+    pub recipient_role: EscrowRole,
+    pub content_hash: [u8; 32],
+    pub timestamp: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expected_vault_balance_follows_status() {
+        let mut escrow_account = Escrow {
+            status: EscrowStatus::Funding,
+            amount: 100,
+            funded_amount: 40,
+            ..Escrow::default()
+        };
+        assert_eq!(escrow_account.expected_vault_balance(), 40);
+
+        escrow_account.status = EscrowStatus::Initialized;
+        assert_eq!(escrow_account.expected_vault_balance(), 100);
+
+        escrow_account.status = EscrowStatus::Disputed;
+        assert_eq!(escrow_account.expected_vault_balance(), 100);
+
+        // Retainage held back from released milestones.
+        escrow_account.retained_amount = 10;
+        assert_eq!(escrow_account.expected_vault_balance(), 110);
+
+        escrow_account.status = EscrowStatus::Withdrawn;
+        assert_eq!(escrow_account.expected_vault_balance(), 10);
+
+        escrow_account.status = EscrowStatus::Refunded;
+        assert_eq!(escrow_account.expected_vault_balance(), 10);
+
+        escrow_account.retained_amount = 0;
+        assert_eq!(escrow_account.expected_vault_balance(), 0);
+
+        // Unclaimed contributions stay in the vault.
+        escrow_account.crowdfunded = true;
+        assert_eq!(escrow_account.expected_vault_balance(), 40);
+    }
+
+    #[test]
+    fn test_escrow_account_layout() {
+        let escrow_account = Escrow {
+            status: EscrowStatus::Refunded,
+            mint: Pubkey::new_from_array([1; 32]),
+            recipient: Pubkey::new_from_array([2; 32]),
+            initializer: Pubkey::new_from_array([3; 32]),
+            arbiter: Pubkey::new_from_array([4; 32]),
+            amount: 0x0102_0304_0506_0708,
+            timeout: -2,
+            vault_bump: 254,
+            escrow_bump: 253,
+            terms_hash: [5; 32],
+            version: 1,
+            ..Escrow::default()
+        };
+
+        let mut data = Vec::new();
+        escrow_account.try_serialize(&mut data).unwrap();
+
+        // The offsets are pinned on purpose: changing them breaks every client
+        // filtering escrows with memcmp.
+        assert_eq!(Escrow::STATUS_OFFSET, 8);
+        assert_eq!(Escrow::MINT_OFFSET, 9);
+        assert_eq!(Escrow::RECIPIENT_OFFSET, 41);
+        assert_eq!(Escrow::INITIALIZER_OFFSET, 73);
+        assert_eq!(Escrow::ARBITER_OFFSET, 105);
+        assert_eq!(Escrow::LEN, 879);
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(Escrow::DISCRIMINATOR);
+        expected.push(2);
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend_from_slice(&[2; 32]);
+        expected.extend_from_slice(&[3; 32]);
+        expected.extend_from_slice(&[4; 32]);
+        expected.extend_from_slice(&0x0102_0304_0506_0708u64.to_le_bytes());
+        expected.extend_from_slice(&(-2i64).to_le_bytes());
+        expected.push(254);
+        expected.push(253);
+        expected.extend_from_slice(&[5; 32]);
+        expected.push(1);
+        // Fields carved out of the reserved space after version 1, plus whatever
+        // is still reserved, are all zero here.
+        expected.resize(8 + Escrow::LEN, 0);
+
+        assert_eq!(data, expected);
+
+        let decoded = Escrow::try_deserialize(&mut data.as_slice()).unwrap();
+        assert_eq!(decoded.status, EscrowStatus::Refunded);
+        assert_eq!(decoded.recipient, Pubkey::new_from_array([2; 32]));
+    }
+
+    #[test]
+    fn test_version_1_escrow_decodes_once_migrated() {
+        // Byte offset of `version`; everything after it was reserved in version 1.
+        const VERSION_OFFSET: usize = 187;
+        // Length of a version 1 account: the fields up to `version`, then 64
+        // reserved bytes.
+        const VERSION_1_LEN: usize = 8 + 180 + 64;
+        assert_eq!(Escrow::RESERVED_LEN, 32);
+
+        // An account written by version 1 code: every byte after `version` is zero.
+        let mut data = vec![0u8; VERSION_1_LEN];
+        data[..8].copy_from_slice(Escrow::DISCRIMINATOR);
+        data[Escrow::STATUS_OFFSET] = 1;
+        data[Escrow::MINT_OFFSET..Escrow::MINT_OFFSET + 32].copy_from_slice(&[9; 32]);
+        data[VERSION_OFFSET] = 1;
+
+        // The layout has grown past the reserved space since, so the account
+        // no longer decodes as it is...
+        assert!(Escrow::try_deserialize(&mut data.as_slice()).is_err());
+
+        // ...but does once `migrate_escrow` has zero-extended it.
+        data.resize(8 + Escrow::LEN, 0);
+        let decoded = Escrow::try_deserialize(&mut data.as_slice()).unwrap();
+        assert_eq!(decoded.version, 1);
+        assert_eq!(decoded.status, EscrowStatus::Withdrawn);
+        assert_eq!(decoded.mint, Pubkey::new_from_array([9; 32]));
+        assert_eq!(decoded.duration, 0);
+        assert_eq!(decoded.funded_amount, 0);
+        assert_eq!(decoded.funding_deadline, 0);
+        assert!(!decoded.crowdfunded);
+        assert_eq!(decoded.min_goal, 0);
+        assert_eq!(decoded.governance_proposal, Pubkey::default());
+        assert!(!decoded.arbiter_accepted);
+        assert_eq!(decoded.arbiter_acceptance_deadline, 0);
+        assert_eq!(decoded.backup_arbiter, Pubkey::default());
+        assert_eq!(decoded.replacement_deadline, 0);
+        assert_eq!(decoded.retainage_bps, 0);
+        assert_eq!(decoded.retained_amount, 0);
+        assert_eq!(decoded.external_id, 0);
+        assert_eq!(decoded.invoice_hash, [0; 32]);
+        assert_eq!(decoded.rationale_hash, [0; 32]);
+        assert_eq!(decoded.withdraw_cosigner, Pubkey::default());
+        assert_eq!(decoded.recovery_authority, Pubkey::default());
+        assert_eq!(decoded.beneficiary_token_account, Pubkey::default());
+        assert_eq!(decoded.beneficiary_window, 0);
+        assert!(!decoded.timeout_warning_emitted);
+        assert_eq!(decoded.heartbeat_increment, 0);
+        assert!(!decoded.mint_freezable);
+        assert!(!decoded.order_seeded);
+        assert!(!decoded.settlement_guard);
+        assert_eq!(decoded.milestone_count, 0);
+        assert_eq!(decoded.release_not_before, 0);
+        assert_eq!(decoded.auditor, Pubkey::default());
+        assert!(!decoded.trade_mode);
+        assert_eq!(decoded.previous_escrow, Pubkey::default());
+        assert_eq!(decoded.milestone_total, 0);
+        assert_eq!(decoded.released_amount, 0);
+        assert_eq!(decoded.timeout_warning_window, 0);
+        assert_eq!(decoded.warning_window(), TIMEOUT_WARNING_WINDOW);
+        assert_eq!(decoded.configured_duration, 0);
+        assert_eq!(decoded.dispute_time_remaining, 0);
+        assert_eq!(decoded.dispute_raised_by, Pubkey::default());
+        assert_eq!(decoded.disputed_milestones, 0);
+        assert_eq!(decoded.rent_collector, Pubkey::default());
+        assert_eq!(decoded.rent_destination(), decoded.initializer);
+        assert_eq!(decoded.dispute_window, 0);
+        assert_eq!(decoded.delivered_at, 0);
+        assert_eq!(decoded.arbiter_registrar, Pubkey::default());
+        assert_eq!(decoded.required_qualifications, 0);
+        assert_eq!(decoded.max_arbiter_fee_bps, 0);
+        assert_eq!(decoded.arbiter_fee_bps, 0);
+        assert_eq!(decoded.template, Pubkey::default());
+
+        // Re-encoding must reproduce the migrated bytes exactly.
+        let mut reencoded = Vec::new();
+        decoded.try_serialize(&mut reencoded).unwrap();
+        assert_eq!(reencoded, data);
+
+        let default_escrow = Escrow::default();
+        assert_eq!(default_escrow.version, 0);
+        assert_eq!(default_escrow.status, EscrowStatus::Initialized);
+    }
 }
@@ -1,13 +1,73 @@
 use anchor_lang::{prelude::*, solana_program::instruction::Instruction, system_program, InstructionData};
-use anchor_spl::token::{self};
+use anchor_spl::associated_token::{self, get_associated_token_address};
+use anchor_spl::token::{self, spl_token};
 use solana_program_test::*;
 use solana_sdk::{
     clock::Clock,
+    program_pack::Pack,
     pubkey::Pubkey,
     signature::{Keypair, Signer},
     transaction::Transaction,
 };
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Program standing in for spl-governance, whose program binary and crate
+/// are not available to program-test here. See `execute_as_treasury`.
+const GOVERNANCE_STUB_ID: Pubkey = Pubkey::new_from_array([0x67; 32]);
+
+/// Executes an instruction the way spl-governance executes the transaction of
+/// a passed proposal: the governance's native treasury, the PDA
+/// `[b"native-treasury", governance]`, signs it through `invoke_signed`.
+/// The accounts are the governance, the program to invoke and then that
+/// instruction's accounts; the data is the instruction's data.
+fn execute_as_treasury(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> anchor_lang::solana_program::entrypoint::ProgramResult {
+    let governance = accounts[0].key;
+    let (treasury, bump) =
+        Pubkey::find_program_address(&[b"native-treasury", governance.as_ref()], program_id);
+    let instruction = Instruction {
+        program_id: *accounts[1].key,
+        accounts: accounts[2..]
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: *account.key,
+                is_signer: account.is_signer || *account.key == treasury,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data: data.to_vec(),
+    };
+    anchor_lang::solana_program::program::invoke_signed(
+        &instruction,
+        accounts,
+        &[&[b"native-treasury", governance.as_ref(), &[bump]]],
+    )
+}
+
+/// Wraps `instruction` for execution by `governance` through the stub. The
+/// treasury's signature is left to the stub.
+fn via_governance(governance: Pubkey, instruction: Instruction) -> Instruction {
+    let (treasury, _) = Pubkey::find_program_address(
+        &[b"native-treasury", governance.as_ref()],
+        &GOVERNANCE_STUB_ID,
+    );
+    let mut accounts = vec![
+        AccountMeta::new_readonly(governance, false),
+        AccountMeta::new_readonly(instruction.program_id, false),
+    ];
+    accounts.extend(instruction.accounts.into_iter().map(|mut account| {
+        account.is_signer &= account.pubkey != treasury;
+        account
+    }));
+    Instruction {
+        program_id: GOVERNANCE_STUB_ID,
+        accounts,
+        data: instruction.data,
+    }
+}
 
 // Test setup
 struct TestContext {
@@ -25,7 +85,21 @@ struct TestContext {
 impl TestContext {
     async fn new() -> Self {
         let program_id = escrow::id();
-        let mut program_test = ProgramTest::new("escrow", program_id, processor!(escrow::entry));
+        // `entry` ties the account slice to the `'info` lifetime, so hand it a
+        // leaked copy that lives for the whole test.
+        let mut program_test = ProgramTest::new(
+            "escrow",
+            program_id,
+            processor!(|program_id, accounts, data| {
+                let accounts = Box::leak(Box::new(accounts.to_vec()));
+                escrow::entry(program_id, accounts, data)
+            }),
+        );
+        program_test.add_program(
+            "governance_stub",
+            GOVERNANCE_STUB_ID,
+            processor!(execute_as_treasury),
+        );
         let mut context = program_test.start_with_context().await;
 
         let initializer = Keypair::new();
@@ -33,8 +107,12 @@ impl TestContext {
         let arbiter = Keypair::new();
         let mint_authority = Keypair::new();
 
-        let mint =
-            Self::create_mint(&mut context, &mint_authority.pubkey(), &mint_authority).await;
+        // The parties pay rent for the accounts they open.
+        for party in [&initializer, &recipient, &arbiter] {
+            Self::airdrop(&mut context, &party.pubkey(), 1_000_000_000).await;
+        }
+
+        let mint = Self::create_mint(&mut context, &mint_authority.pubkey()).await;
 
         let initializer_token_account = Self::create_token_account(
             &mut context,
@@ -67,10 +145,23 @@ impl TestContext {
         }
     }
 
+    async fn airdrop(context: &mut ProgramTestContext, to: &Pubkey, lamports: u64) {
+        let tx = Transaction::new_signed_with_payer(
+            &[solana_sdk::system_instruction::transfer(
+                &context.payer.pubkey(),
+                to,
+                lamports,
+            )],
+            Some(&context.payer.pubkey()),
+            &[&context.payer],
+            context.last_blockhash,
+        );
+        context.banks_client.process_transaction(tx).await.unwrap();
+    }
+
     async fn create_mint(
         context: &mut ProgramTestContext,
         authority: &Pubkey,
-        payer: &Keypair,
     ) -> Pubkey {
         let mint = Keypair::new();
         let rent = context.banks_client.get_rent().await.unwrap();
@@ -147,10 +238,11 @@ impl TestContext {
         token_account.pubkey()
     }
 
-    async fn get_token_balance(&mut self, account: &Pubkey) -> u64 {
+    async fn get_token_balance(&self, account: &Pubkey) -> u64 {
         let account_info = self
             .context
             .banks_client
+            .clone()
             .get_account(*account)
             .await
             .unwrap()
@@ -160,21 +252,393 @@ impl TestContext {
     }
 
     async fn get_account<T: anchor_lang::AccountDeserialize>(
-        &mut self,
+        &self,
         address: &Pubkey,
     ) -> Option<T> {
         self.context
             .banks_client
+            .clone()
             .get_account(*address)
             .await
             .unwrap()
             .map(|acc| T::try_deserialize(&mut acc.data.as_slice()).unwrap())
     }
+
+    /// Moves the bank clock forward by `seconds`.
+    async fn advance_clock(&mut self, seconds: i64) {
+        let mut clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp += seconds;
+        self.context.set_sysvar(&clock);
+        self.refresh_blockhash().await;
+    }
+
+    /// Fetches a fresh blockhash so a retried transaction is not deduplicated
+    /// against an earlier, failed attempt.
+    async fn refresh_blockhash(&mut self) {
+        self.context.last_blockhash = self
+            .context
+            .banks_client
+            .get_new_latest_blockhash(&self.context.last_blockhash)
+            .await
+            .unwrap();
+    }
+
+    /// Returns the escrow state PDA and its vault for the default parties.
+    fn escrow_addresses(&self) -> (Pubkey, Pubkey) {
+        let (escrow_state_pda, _) = Pubkey::find_program_address(
+            &[
+                b"escrow",
+                self.initializer.pubkey().as_ref(),
+                self.recipient.pubkey().as_ref(),
+            ],
+            &self.program_id,
+        );
+        let vault_pda = get_associated_token_address(&escrow_state_pda, &self.mint);
+        (escrow_state_pda, vault_pda)
+    }
+
+    /// Returns the `MintStats` PDA of the test mint.
+    fn mint_stats_address(&self) -> Pubkey {
+        Pubkey::find_program_address(&[b"mint_stats", self.mint.as_ref()], &self.program_id).0
+    }
+
+    /// Builds an `initialize` instruction for the default parties.
+    fn initialize_ix(&self, amount: u64, timeout: i64, terms_hash: [u8; 32]) -> Instruction {
+        let (escrow_state_pda, vault_pda) = self.escrow_addresses();
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::Initialize {
+                initializer: self.initializer.pubkey(),
+                recipient: self.recipient.pubkey(),
+                arbiter: Some(self.arbiter.pubkey()),
+                mint: self.mint,
+                initializer_deposit_token_account: self.initializer_token_account,
+                escrow_state: escrow_state_pda,
+                vault: vault_pda,
+                mint_stats: self.mint_stats_address(),
+                system_program: system_program::ID,
+                token_program: token::ID,
+                associated_token_program: associated_token::ID,
+                withdraw_cosigner: None,
+                recovery_authority: None,
+                recovery_token_account: None,
+                rent_collector: None,
+                arbiter_registrar: None,
+                template: None,
+                initializer_profile: None,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::Initialize {
+                amount,
+                timeout,
+                terms_hash,
+                external_id: 0,
+                invoice_hash: [0; 32],
+                order_seeded: false,
+                release_not_before: 0,
+                reject_freezable: false,
+                timeout_warning_window: timeout,
+                dispute_window: 0,
+                required_qualifications: 0,
+                max_arbiter_fee_bps: 0,
+            }
+            .data(),
+        }
+    }
+
+    /// Builds a `create_escrow` instruction for the default parties.
+    fn create_escrow_ix(
+        &self,
+        amount: u64,
+        timeout: i64,
+        funding_period: i64,
+        crowdfunded: bool,
+        min_goal: u64,
+    ) -> Instruction {
+        let (escrow_state_pda, vault_pda) = self.escrow_addresses();
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::CreateEscrow {
+                initializer: self.initializer.pubkey(),
+                recipient: self.recipient.pubkey(),
+                arbiter: Some(self.arbiter.pubkey()),
+                mint: self.mint,
+                escrow_state: escrow_state_pda,
+                vault: vault_pda,
+                mint_stats: self.mint_stats_address(),
+                system_program: system_program::ID,
+                token_program: token::ID,
+                associated_token_program: associated_token::ID,
+                governance_proposal: None,
+                withdraw_cosigner: None,
+                governance_program: None,
+                rent_collector: None,
+                arbiter_registrar: None,
+                template: None,
+                initializer_profile: None,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::CreateEscrow {
+                amount,
+                timeout,
+                terms_hash: [0; 32],
+                funding_period,
+                crowdfunded,
+                min_goal,
+                retainage_bps: 0,
+                warranty_period: 0,
+                external_id: 0,
+                invoice_hash: [0; 32],
+                release_not_before: 0,
+                reject_freezable: false,
+                timeout_warning_window: timeout,
+                dispute_window: 0,
+                required_qualifications: 0,
+                max_arbiter_fee_bps: 0,
+            }
+            .data(),
+        }
+    }
+
+    /// Builds an `accept_arbiter_role` instruction for the default arbiter.
+    fn accept_arbiter_ix(&self) -> Instruction {
+        let (escrow_state_pda, _) = self.escrow_addresses();
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::AcceptArbiterRole {
+                arbiter: self.arbiter.pubkey(),
+                escrow_state: escrow_state_pda,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::AcceptArbiterRole {}.data(),
+        }
+    }
+
+    /// Builds a `raise_dispute` instruction, raised by the default recipient.
+    fn raise_dispute_ix(&self) -> Instruction {
+        let (escrow_state_pda, _) = self.escrow_addresses();
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::RaiseDispute {
+                party: self.recipient.pubkey(),
+                escrow_state: escrow_state_pda,
+                party_profile: None,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::RaiseDispute { reason_code: 1 }.data(),
+        }
+    }
+
+    /// Builds a `fund_escrow` instruction for the default parties.
+    fn fund_escrow_ix(&self, amount: u64) -> Instruction {
+        let (escrow_state_pda, vault_pda) = self.escrow_addresses();
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::FundEscrow {
+                initializer: self.initializer.pubkey(),
+                initializer_deposit_token_account: self.initializer_token_account,
+                escrow_state: escrow_state_pda,
+                vault: vault_pda,
+                mint: self.mint,
+                mint_stats: self.mint_stats_address(),
+                token_program: token::ID,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::FundEscrow { amount }.data(),
+        }
+    }
+
+    /// Builds a `contribute` instruction for `contributor`.
+    fn contribute_ix(&self, contributor: &Pubkey, token_account: &Pubkey, amount: u64) -> Instruction {
+        let (escrow_state_pda, vault_pda) = self.escrow_addresses();
+        let (contribution_pda, _) = Pubkey::find_program_address(
+            &[b"contribution", escrow_state_pda.as_ref(), contributor.as_ref()],
+            &self.program_id,
+        );
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::Contribute {
+                contributor: *contributor,
+                contributor_token_account: *token_account,
+                escrow_state: escrow_state_pda,
+                contribution: contribution_pda,
+                vault: vault_pda,
+                mint: self.mint,
+                mint_stats: self.mint_stats_address(),
+                system_program: system_program::ID,
+                token_program: token::ID,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::Contribute { amount }.data(),
+        }
+    }
+
+    /// Builds a `claim_contribution` instruction for `contributor`.
+    fn claim_contribution_ix(&self, contributor: &Pubkey, token_account: &Pubkey) -> Instruction {
+        let (escrow_state_pda, vault_pda) = self.escrow_addresses();
+        let (contribution_pda, _) = Pubkey::find_program_address(
+            &[b"contribution", escrow_state_pda.as_ref(), contributor.as_ref()],
+            &self.program_id,
+        );
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::ClaimContribution {
+                contributor: *contributor,
+                contributor_refund_token_account: *token_account,
+                escrow_state: escrow_state_pda,
+                contribution: contribution_pda,
+                vault: vault_pda,
+                mint: self.mint,
+                mint_stats: self.mint_stats_address(),
+                token_program: token::ID,
+                instructions_sysvar: None,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::ClaimContribution {}.data(),
+        }
+    }
+
+    /// Builds a `withdraw` instruction for the default recipient.
+    fn withdraw_ix(&self, governance_proposal: Option<Pubkey>) -> Instruction {
+        let (escrow_state_pda, vault_pda) = self.escrow_addresses();
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::Withdraw {
+                recipient: self.recipient.pubkey(),
+                recipient_deposit_token_account: self.recipient_token_account,
+                escrow_state: escrow_state_pda,
+                vault: vault_pda,
+                mint: self.mint,
+                mint_stats: self.mint_stats_address(),
+                token_program: token::ID,
+                governance_proposal,
+                withdraw_cosigner: None,
+                instructions_sysvar: None,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::Withdraw {}.data(),
+        }
+    }
+
+    /// Builds a `cancel` instruction for the default parties.
+    fn cancel_ix(&self) -> Instruction {
+        let (escrow_state_pda, vault_pda) = self.escrow_addresses();
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::Cancel {
+                initializer: self.initializer.pubkey(),
+                initializer_refund_token_account: self.initializer_token_account,
+                escrow_state: escrow_state_pda,
+                vault: vault_pda,
+                mint: self.mint,
+                mint_stats: self.mint_stats_address(),
+                token_program: token::ID,
+                instructions_sysvar: None,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::Cancel {}.data(),
+        }
+    }
+
+    /// Builds a `refund` instruction for the default escrow.
+    fn refund_ix(&self) -> Instruction {
+        let (escrow_state_pda, vault_pda) = self.escrow_addresses();
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::Refund {
+                initializer: self.initializer.pubkey(),
+                initializer_refund_token_account: self.initializer_token_account,
+                escrow_state: escrow_state_pda,
+                vault: vault_pda,
+                mint: self.mint,
+                mint_stats: self.mint_stats_address(),
+                token_program: token::ID,
+                instructions_sysvar: None,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::Refund {}.data(),
+        }
+    }
+
+    /// Returns the address of the default escrow's milestone at `index`.
+    fn milestone_address(&self, index: u8) -> Pubkey {
+        let (escrow_state_pda, _) = self.escrow_addresses();
+        Pubkey::find_program_address(
+            &[b"milestone", escrow_state_pda.as_ref(), &[index]],
+            &self.program_id,
+        )
+        .0
+    }
+
+    /// Builds an `add_milestone` instruction for the default escrow.
+    fn add_milestone_ix(&self, index: u8, amount: u64) -> Instruction {
+        let (escrow_state_pda, _) = self.escrow_addresses();
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::AddMilestone {
+                initializer: self.initializer.pubkey(),
+                escrow_state: escrow_state_pda,
+                milestone: self.milestone_address(index),
+                arbiter: None,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::AddMilestone {
+                index,
+                amount,
+                deadline: 0,
+            }
+            .data(),
+        }
+    }
+
+    /// Builds a `release_milestone` instruction for the default escrow.
+    fn release_milestone_ix(
+        &self,
+        index: u8,
+        authority: &Pubkey,
+        instructions_sysvar: Option<Pubkey>,
+    ) -> Instruction {
+        let (escrow_state_pda, vault_pda) = self.escrow_addresses();
+        Instruction {
+            program_id: self.program_id,
+            accounts: escrow::accounts::ReleaseMilestone {
+                authority: *authority,
+                escrow_state: escrow_state_pda,
+                milestone: self.milestone_address(index),
+                vault: vault_pda,
+                mint: self.mint,
+                mint_stats: self.mint_stats_address(),
+                recipient_deposit_token_account: self.recipient_token_account,
+                token_program: token::ID,
+                instructions_sysvar,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::ReleaseMilestone {}.data(),
+        }
+    }
+}
+
+/// Signs `instructions` with the payer plus `signers` and processes them.
+async fn process(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> std::result::Result<(), BanksClientError> {
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&context.payer.pubkey()),
+        all_signers.as_slice(),
+        context.last_blockhash,
+    );
+    context.banks_client.process_transaction(tx).await
 }
 
 #[tokio::test]
 async fn test_initialize_and_withdraw() {
-    let mut test_harness = TestContext::new().await;
+    let test_harness = TestContext::new().await;
 
     let amount = 50;
     let timeout =
@@ -189,26 +653,46 @@ async fn test_initialize_and_withdraw() {
         &test_harness.program_id,
     );
 
-    let (vault_pda, _) = Pubkey::find_program_address(
-        &[b"vault", escrow_state_pda.as_ref()],
-        &test_harness.program_id,
-    );
+    let vault_pda = get_associated_token_address(&escrow_state_pda, &test_harness.mint);
 
     let init_ix = Instruction {
         program_id: test_harness.program_id,
         accounts: escrow::accounts::Initialize {
             initializer: test_harness.initializer.pubkey(),
             recipient: test_harness.recipient.pubkey(),
-            arbiter: test_harness.arbiter.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
             mint: test_harness.mint,
             initializer_deposit_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
-            system_program: system_program::id(),
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
             token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
         }
         .to_account_metas(None),
-        data: escrow::instruction::Initialize { amount, timeout }.data(),
+        data: escrow::instruction::Initialize {
+            amount,
+            timeout,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: timeout,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
     };
 
     let tx = Transaction::new_signed_with_payer(
@@ -239,7 +723,12 @@ async fn test_initialize_and_withdraw() {
             recipient_deposit_token_account: test_harness.recipient_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
             token_program: token::ID,
+            governance_proposal: None,
+            withdraw_cosigner: None,
+            instructions_sysvar: None,
         }
         .to_account_metas(None),
         data: escrow::instruction::Withdraw {}.data(),
@@ -285,26 +774,46 @@ async fn test_initialize_and_refund() {
         &test_harness.program_id,
     );
 
-    let (vault_pda, _) = Pubkey::find_program_address(
-        &[b"vault", escrow_state_pda.as_ref()],
-        &test_harness.program_id,
-    );
+    let vault_pda = get_associated_token_address(&escrow_state_pda, &test_harness.mint);
 
     let init_ix = Instruction {
         program_id: test_harness.program_id,
         accounts: escrow::accounts::Initialize {
             initializer: test_harness.initializer.pubkey(),
             recipient: test_harness.recipient.pubkey(),
-            arbiter: test_harness.arbiter.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
             mint: test_harness.mint,
             initializer_deposit_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
-            system_program: system_program::id(),
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
             token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
         }
         .to_account_metas(None),
-        data: escrow::instruction::Initialize { amount, timeout }.data(),
+        data: escrow::instruction::Initialize {
+            amount,
+            timeout,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: timeout,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
     };
 
     let tx = Transaction::new_signed_with_payer(
@@ -320,7 +829,7 @@ async fn test_initialize_and_refund() {
         .await
         .unwrap();
 
-    tokio::time::sleep(Duration::from_secs(2)).await;
+    test_harness.advance_clock(2).await;
 
     let refund_ix = Instruction {
         program_id: test_harness.program_id,
@@ -329,7 +838,10 @@ async fn test_initialize_and_refund() {
             initializer_refund_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
             token_program: token::ID,
+            instructions_sysvar: None,
         }
         .to_account_metas(None),
         data: escrow::instruction::Refund {}.data(),
@@ -358,10 +870,135 @@ async fn test_initialize_and_refund() {
     assert_eq!(escrow_account.status, escrow::EscrowStatus::Refunded);
 }
 
+#[tokio::test]
+async fn test_initialize_with_precreated_vault() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    // Anyone can create the escrow's associated token account ahead of time.
+    let payer = test_harness.context.payer.pubkey();
+    let create_ata_ix =
+        associated_token::spl_associated_token_account::instruction::create_associated_token_account(
+            &payer,
+            &escrow_state_pda,
+            &test_harness.mint,
+            &token::ID,
+        );
+    process(&mut test_harness.context, &[create_ata_ix], &[])
+        .await
+        .unwrap();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 50);
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Initialized);
+}
+
+#[tokio::test]
+async fn test_initialize_rejects_freezable_mint_on_request() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+
+    // A mint whose freeze authority could freeze the vault.
+    let mint = Keypair::new();
+    let rent = test_harness.context.banks_client.get_rent().await.unwrap();
+    let payer = test_harness.context.payer.pubkey();
+    let mint_authority = test_harness.mint_authority.pubkey();
+    let create_mint_ixs = [
+        solana_sdk::system_instruction::create_account(
+            &payer,
+            &mint.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &mint_authority,
+            Some(&mint_authority),
+            0,
+        )
+        .unwrap(),
+    ];
+    process(&mut test_harness.context, &create_mint_ixs, &[&mint])
+        .await
+        .unwrap();
+    let deposit_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &mint.pubkey(),
+        &test_harness.initializer.pubkey(),
+        &test_harness.mint_authority,
+        100,
+    )
+    .await;
+
+    let init_ix = |reject_freezable: bool| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Initialize {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
+            mint: mint.pubkey(),
+            initializer_deposit_token_account: deposit_account,
+            escrow_state: escrow_state_pda,
+            vault: get_associated_token_address(&escrow_state_pda, &mint.pubkey()),
+            mint_stats: Pubkey::find_program_address(
+                &[b"mint_stats", mint.pubkey().as_ref()],
+                &test_harness.program_id,
+            )
+            .0,
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Initialize {
+            amount: 50,
+            timeout: 100,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable,
+            timeout_warning_window: 100,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+
+    assert!(
+        process(&mut test_harness.context, &[init_ix(true)], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    // Without the flag the escrow opens and only records the risk.
+    process(&mut test_harness.context, &[init_ix(false)], &[&test_harness.initializer])
+        .await
+        .unwrap();
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert!(escrow_account.mint_freezable);
+}
+
 #[tokio::test]
 #[should_panic]
 async fn test_initialize_with_zero_amount() {
-    let mut test_harness = TestContext::new().await;
+    let test_harness = TestContext::new().await;
     let (escrow_state_pda, _) = Pubkey::find_program_address(
         &[
             b"escrow",
@@ -370,28 +1007,44 @@ async fn test_initialize_with_zero_amount() {
         ],
         &test_harness.program_id,
     );
-    let (vault_pda, _) = Pubkey::find_program_address(
-        &[b"vault", escrow_state_pda.as_ref()],
-        &test_harness.program_id,
-    );
+    let vault_pda = get_associated_token_address(&escrow_state_pda, &test_harness.mint);
 
     let init_ix = Instruction {
         program_id: test_harness.program_id,
         accounts: escrow::accounts::Initialize {
             initializer: test_harness.initializer.pubkey(),
             recipient: test_harness.recipient.pubkey(),
-            arbiter: test_harness.arbiter.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
             mint: test_harness.mint,
             initializer_deposit_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
-            system_program: system_program::id(),
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
             token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
         }
         .to_account_metas(None),
         data: escrow::instruction::Initialize {
             amount: 0,
             timeout: 10,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: 10,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
         }
         .data(),
     };
@@ -408,7 +1061,7 @@ async fn test_initialize_with_zero_amount() {
 #[tokio::test]
 #[should_panic]
 async fn test_initialize_with_self_as_recipient() {
-    let mut test_harness = TestContext::new().await;
+    let test_harness = TestContext::new().await;
     let (escrow_state_pda, _) = Pubkey::find_program_address(
         &[
             b"escrow",
@@ -417,28 +1070,44 @@ async fn test_initialize_with_self_as_recipient() {
         ],
         &test_harness.program_id,
     );
-    let (vault_pda, _) = Pubkey::find_program_address(
-        &[b"vault", escrow_state_pda.as_ref()],
-        &test_harness.program_id,
-    );
+    let vault_pda = get_associated_token_address(&escrow_state_pda, &test_harness.mint);
 
     let init_ix = Instruction {
         program_id: test_harness.program_id,
         accounts: escrow::accounts::Initialize {
             initializer: test_harness.initializer.pubkey(),
             recipient: test_harness.initializer.pubkey(),
-            arbiter: test_harness.arbiter.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
             mint: test_harness.mint,
             initializer_deposit_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
-            system_program: system_program::id(),
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
             token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
         }
         .to_account_metas(None),
         data: escrow::instruction::Initialize {
             amount: 10,
             timeout: 10,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: 10,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
         }
         .data(),
     };
@@ -468,26 +1137,46 @@ async fn test_withdraw_after_timeout() {
         &test_harness.program_id,
     );
 
-    let (vault_pda, _) = Pubkey::find_program_address(
-        &[b"vault", escrow_state_pda.as_ref()],
-        &test_harness.program_id,
-    );
+    let vault_pda = get_associated_token_address(&escrow_state_pda, &test_harness.mint);
 
     let init_ix = Instruction {
         program_id: test_harness.program_id,
         accounts: escrow::accounts::Initialize {
             initializer: test_harness.initializer.pubkey(),
             recipient: test_harness.recipient.pubkey(),
-            arbiter: test_harness.arbiter.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
             mint: test_harness.mint,
             initializer_deposit_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
-            system_program: system_program::id(),
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
             token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
         }
         .to_account_metas(None),
-        data: escrow::instruction::Initialize { amount, timeout }.data(),
+        data: escrow::instruction::Initialize {
+            amount,
+            timeout,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: timeout,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
     };
 
     let tx = Transaction::new_signed_with_payer(
@@ -503,7 +1192,7 @@ async fn test_withdraw_after_timeout() {
         .await
         .unwrap();
 
-    tokio::time::sleep(Duration::from_secs(2)).await;
+    test_harness.advance_clock(2).await;
 
     let withdraw_ix = Instruction {
         program_id: test_harness.program_id,
@@ -512,7 +1201,12 @@ async fn test_withdraw_after_timeout() {
             recipient_deposit_token_account: test_harness.recipient_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
             token_program: token::ID,
+            governance_proposal: None,
+            withdraw_cosigner: None,
+            instructions_sysvar: None,
         }
         .to_account_metas(None),
         data: escrow::instruction::Withdraw {}.data(),
@@ -535,7 +1229,7 @@ async fn test_withdraw_after_timeout() {
 #[tokio::test]
 #[should_panic]
 async fn test_refund_before_timeout() {
-    let mut test_harness = TestContext::new().await;
+    let test_harness = TestContext::new().await;
     let amount = 50;
     let timeout = 10;
 
@@ -548,26 +1242,46 @@ async fn test_refund_before_timeout() {
         &test_harness.program_id,
     );
 
-    let (vault_pda, _) = Pubkey::find_program_address(
-        &[b"vault", escrow_state_pda.as_ref()],
-        &test_harness.program_id,
-    );
+    let vault_pda = get_associated_token_address(&escrow_state_pda, &test_harness.mint);
 
     let init_ix = Instruction {
         program_id: test_harness.program_id,
         accounts: escrow::accounts::Initialize {
             initializer: test_harness.initializer.pubkey(),
             recipient: test_harness.recipient.pubkey(),
-            arbiter: test_harness.arbiter.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
             mint: test_harness.mint,
             initializer_deposit_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
-            system_program: system_program::id(),
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
             token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
         }
         .to_account_metas(None),
-        data: escrow::instruction::Initialize { amount, timeout }.data(),
+        data: escrow::instruction::Initialize {
+            amount,
+            timeout,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: timeout,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
     };
 
     let tx = Transaction::new_signed_with_payer(
@@ -590,7 +1304,10 @@ async fn test_refund_before_timeout() {
             initializer_refund_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
             token_program: token::ID,
+            instructions_sysvar: None,
         }
         .to_account_metas(None),
         data: escrow::instruction::Refund {}.data(),
@@ -613,7 +1330,7 @@ async fn test_refund_before_timeout() {
 #[tokio::test]
 #[should_panic]
 async fn test_withdraw_with_invalid_recipient() {
-    let mut test_harness = TestContext::new().await;
+    let test_harness = TestContext::new().await;
     let amount = 50;
     let timeout = 10;
 
@@ -626,26 +1343,46 @@ async fn test_withdraw_with_invalid_recipient() {
         &test_harness.program_id,
     );
 
-    let (vault_pda, _) = Pubkey::find_program_address(
-        &[b"vault", escrow_state_pda.as_ref()],
-        &test_harness.program_id,
-    );
+    let vault_pda = get_associated_token_address(&escrow_state_pda, &test_harness.mint);
 
     let init_ix = Instruction {
         program_id: test_harness.program_id,
         accounts: escrow::accounts::Initialize {
             initializer: test_harness.initializer.pubkey(),
             recipient: test_harness.recipient.pubkey(),
-            arbiter: test_harness.arbiter.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
             mint: test_harness.mint,
             initializer_deposit_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
-            system_program: system_program::id(),
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
             token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
         }
         .to_account_metas(None),
-        data: escrow::instruction::Initialize { amount, timeout }.data(),
+        data: escrow::instruction::Initialize {
+            amount,
+            timeout,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: timeout,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
     };
 
     let tx = Transaction::new_signed_with_payer(
@@ -670,7 +1407,12 @@ async fn test_withdraw_with_invalid_recipient() {
             recipient_deposit_token_account: test_harness.recipient_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
             token_program: token::ID,
+            governance_proposal: None,
+            withdraw_cosigner: None,
+            instructions_sysvar: None,
         }
         .to_account_metas(None),
         data: escrow::instruction::Withdraw {}.data(),
@@ -692,7 +1434,7 @@ async fn test_withdraw_with_invalid_recipient() {
 
 #[tokio::test]
 async fn test_cancel_escrow() {
-    let mut test_harness = TestContext::new().await;
+    let test_harness = TestContext::new().await;
     let amount = 50;
     let timeout = 100; // Long timeout
 
@@ -705,26 +1447,46 @@ async fn test_cancel_escrow() {
         &test_harness.program_id,
     );
 
-    let (vault_pda, _) = Pubkey::find_program_address(
-        &[b"vault", escrow_state_pda.as_ref()],
-        &test_harness.program_id,
-    );
+    let vault_pda = get_associated_token_address(&escrow_state_pda, &test_harness.mint);
 
     let init_ix = Instruction {
         program_id: test_harness.program_id,
         accounts: escrow::accounts::Initialize {
             initializer: test_harness.initializer.pubkey(),
             recipient: test_harness.recipient.pubkey(),
-            arbiter: test_harness.arbiter.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
             mint: test_harness.mint,
             initializer_deposit_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
-            system_program: system_program::id(),
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
             token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
         }
         .to_account_metas(None),
-        data: escrow::instruction::Initialize { amount, timeout }.data(),
+        data: escrow::instruction::Initialize {
+            amount,
+            timeout,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: timeout,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
     };
 
     let tx = Transaction::new_signed_with_payer(
@@ -742,7 +1504,10 @@ async fn test_cancel_escrow() {
             initializer_refund_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
             token_program: token::ID,
+            instructions_sysvar: None,
         }
         .to_account_metas(None),
         data: escrow::instruction::Cancel {}.data(),
@@ -768,7 +1533,7 @@ async fn test_cancel_escrow() {
 
 #[tokio::test]
 async fn test_resolve_by_arbiter_to_recipient() {
-    let mut test_harness = TestContext::new().await;
+    let test_harness = TestContext::new().await;
     let amount = 50;
     let timeout = 100;
 
@@ -781,32 +1546,60 @@ async fn test_resolve_by_arbiter_to_recipient() {
         &test_harness.program_id,
     );
 
-    let (vault_pda, _) = Pubkey::find_program_address(
-        &[b"vault", escrow_state_pda.as_ref()],
-        &test_harness.program_id,
-    );
+    let vault_pda = get_associated_token_address(&escrow_state_pda, &test_harness.mint);
 
     let init_ix = Instruction {
         program_id: test_harness.program_id,
         accounts: escrow::accounts::Initialize {
             initializer: test_harness.initializer.pubkey(),
             recipient: test_harness.recipient.pubkey(),
-            arbiter: test_harness.arbiter.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
             mint: test_harness.mint,
             initializer_deposit_token_account: test_harness.initializer_token_account,
             escrow_state: escrow_state_pda,
             vault: vault_pda,
-            system_program: system_program::id(),
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
             token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
         }
         .to_account_metas(None),
-        data: escrow::instruction::Initialize { amount, timeout }.data(),
+        data: escrow::instruction::Initialize {
+            amount,
+            timeout,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: timeout,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
     };
 
+    let accept_ix = test_harness.accept_arbiter_ix();
+    let dispute_ix = test_harness.raise_dispute_ix();
+
     let tx = Transaction::new_signed_with_payer(
-        &[init_ix],
+        &[init_ix, accept_ix, dispute_ix],
         Some(&test_harness.context.payer.pubkey()),
-        &[&test_harness.context.payer, &test_harness.initializer],
+        &[
+            &test_harness.context.payer,
+            &test_harness.initializer,
+            &test_harness.arbiter,
+            &test_harness.recipient,
+        ],
         test_harness.context.last_blockhash,
     );
     test_harness.context.banks_client.process_transaction(tx).await.unwrap();
@@ -817,12 +1610,19 @@ async fn test_resolve_by_arbiter_to_recipient() {
             arbiter: test_harness.arbiter.pubkey(),
             escrow_state: escrow_state_pda,
             vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
             recipient_deposit_token_account: test_harness.recipient_token_account,
             initializer_refund_token_account: test_harness.initializer_token_account,
             token_program: token::ID,
+            instructions_sysvar: None,
         }
         .to_account_metas(None),
-        data: escrow::instruction::ResolveByArbiter { release_to_recipient: true }.data(),
+        data: escrow::instruction::ResolveByArbiter {
+            release_to_recipient: true,
+            rationale_hash: [8; 32],
+        }
+        .data(),
     };
 
     let tx = Transaction::new_signed_with_payer(
@@ -841,4 +1641,4419 @@ async fn test_resolve_by_arbiter_to_recipient() {
     );
     let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
     assert_eq!(escrow_account.status, escrow::EscrowStatus::Withdrawn);
+    assert_eq!(escrow_account.rationale_hash, [8; 32]);
+}
+
+#[tokio::test]
+async fn test_dispute_freezes_escrow_until_resolved() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    let resolve_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::ResolveByArbiter {
+            arbiter: test_harness.arbiter.pubkey(),
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            initializer_refund_token_account: test_harness.initializer_token_account,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::ResolveByArbiter {
+            release_to_recipient: false,
+            rationale_hash: [8; 32],
+        }
+        .data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, accept_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    // Without a dispute the arbiter cannot move the funds.
+    assert!(
+        process(&mut test_harness.context, std::slice::from_ref(&resolve_ix), &[&test_harness.arbiter])
+            .await
+            .is_err()
+    );
+
+    let dispute_ix = test_harness.raise_dispute_ix();
+    process(&mut test_harness.context, &[dispute_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Disputed);
+
+    // Neither party can settle a disputed escrow on their own.
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    assert!(
+        process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+    let cancel_ix = test_harness.cancel_ix();
+    assert!(
+        process(&mut test_harness.context, &[cancel_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    test_harness.refresh_blockhash().await;
+    process(&mut test_harness.context, &[resolve_ix], &[&test_harness.arbiter])
+        .await
+        .unwrap();
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Refunded);
+}
+
+#[tokio::test]
+async fn test_dispute_pauses_the_timeout() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(
+        &mut test_harness.context,
+        &[init_ix, accept_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+    test_harness.advance_clock(60).await;
+
+    let dispute_ix = test_harness.raise_dispute_ix();
+    process(&mut test_harness.context, &[dispute_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    let clock: Clock = test_harness.context.banks_client.get_sysvar().await.unwrap();
+    assert_eq!(
+        escrow_account.dispute_time_remaining,
+        escrow_account.timeout - clock.unix_timestamp
+    );
+    assert!(escrow_account.dispute_time_remaining <= 40);
+
+    // The timeout passing during the dispute does not open the refund path.
+    test_harness.advance_clock(200).await;
+    let refund_ix = test_harness.refund_ix();
+    assert!(
+        process(&mut test_harness.context, &[refund_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_party_handle_is_set_used_and_cleared() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let profile_address = |owner: Pubkey| {
+        Pubkey::find_program_address(&[b"party_profile", owner.as_ref()], &escrow::id()).0
+    };
+    let recipient = test_harness.recipient.pubkey();
+    let recipient_profile = profile_address(recipient);
+    let set_handle_ix = |handle: &str| Instruction {
+        program_id: escrow::id(),
+        accounts: escrow::accounts::SetHandle {
+            owner: recipient,
+            profile: recipient_profile,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::SetHandle {
+            handle: handle.to_string(),
+        }
+        .data(),
+    };
+
+    for handle in ["", "acme marketplace", &"a".repeat(escrow::MAX_HANDLE_LEN + 1)] {
+        assert!(
+            process(&mut test_harness.context, &[set_handle_ix(handle)], &[&test_harness.recipient])
+                .await
+                .is_err()
+        );
+    }
+    process(
+        &mut test_harness.context,
+        &[set_handle_ix("acme"), set_handle_ix("acme-marketplace")],
+        &[&test_harness.recipient],
+    )
+    .await
+    .unwrap();
+    let profile = test_harness
+        .get_account::<escrow::PartyProfile>(&recipient_profile)
+        .await
+        .unwrap();
+    assert_eq!(profile.owner, recipient);
+    assert_eq!(profile.handle, "acme-marketplace");
+
+    // A dispute can only echo the raiser's own handle.
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(
+        &mut test_harness.context,
+        &[init_ix, accept_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+    let raise_ix = |party: Pubkey| Instruction {
+        program_id: escrow::id(),
+        accounts: escrow::accounts::RaiseDispute {
+            party,
+            escrow_state: escrow_state_pda,
+            party_profile: Some(recipient_profile),
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::RaiseDispute { reason_code: 1 }.data(),
+    };
+    let by_initializer_ix = raise_ix(test_harness.initializer.pubkey());
+    let by_recipient_ix = raise_ix(recipient);
+    assert!(
+        process(&mut test_harness.context, &[by_initializer_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+    process(&mut test_harness.context, &[by_recipient_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+
+    let clear_ix = Instruction {
+        program_id: escrow::id(),
+        accounts: escrow::accounts::ClearHandle {
+            owner: recipient,
+            profile: recipient_profile,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::ClearHandle {}.data(),
+    };
+    process(&mut test_harness.context, &[clear_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+    assert!(
+        test_harness
+            .context
+            .banks_client
+            .get_account(recipient_profile)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_raiser_can_withdraw_dispute() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let withdraw_dispute_ix = |party: Pubkey| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::WithdrawDispute {
+            party,
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::WithdrawDispute {}.data(),
+    };
+    let by_initializer_ix = withdraw_dispute_ix(test_harness.initializer.pubkey());
+    let by_recipient_ix = withdraw_dispute_ix(test_harness.recipient.pubkey());
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    let dispute_ix = test_harness.raise_dispute_ix();
+    process(
+        &mut test_harness.context,
+        &[init_ix, accept_ix, dispute_ix],
+        &[&test_harness.initializer, &test_harness.arbiter, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+    let disputed = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(disputed.dispute_raised_by, test_harness.recipient.pubkey());
+
+    // Only the recipient raised it, so only they can drop it.
+    assert!(
+        process(&mut test_harness.context, &[by_initializer_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    // The clock stood still for the 500 seconds the dispute was open.
+    test_harness.advance_clock(500).await;
+    process(&mut test_harness.context, &[by_recipient_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Initialized);
+    assert_eq!(escrow_account.timeout, disputed.timeout + 500);
+    assert_eq!(escrow_account.dispute_time_remaining, 0);
+    assert_eq!(escrow_account.dispute_raised_by, Pubkey::default());
+
+    // With the dispute gone the recipient can withdraw as usual.
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_resolve_split_pays_both_parties() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    let dispute_ix = test_harness.raise_dispute_ix();
+    let split_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::ResolveByArbiter {
+            arbiter: test_harness.arbiter.pubkey(),
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            initializer_refund_token_account: test_harness.initializer_token_account,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::ResolveSplit {
+            bps_to_recipient: 7_000,
+            rationale_hash: [8; 32],
+        }
+        .data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, accept_ix, dispute_ix, split_ix],
+        &[
+            &test_harness.initializer,
+            &test_harness.arbiter,
+            &test_harness.recipient,
+        ],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        35
+    );
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.initializer_token_account)
+            .await,
+        65
+    );
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 0);
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Withdrawn);
+    assert_eq!(escrow_account.rationale_hash, [8; 32]);
+}
+
+#[tokio::test]
+async fn test_resolve_batch_settles_each_escrow() {
+    let mut test_harness = TestContext::new().await;
+    let (first_escrow, first_vault) = test_harness.escrow_addresses();
+    let order_id: u64 = 7;
+    let (second_escrow, _) = Pubkey::find_program_address(
+        &[
+            b"escrow",
+            test_harness.initializer.pubkey().as_ref(),
+            &order_id.to_le_bytes(),
+        ],
+        &test_harness.program_id,
+    );
+    let second_vault = get_associated_token_address(&second_escrow, &test_harness.mint);
+
+    let first_init_ix = test_harness.initialize_ix(40, 100, [0; 32]);
+    let second_init_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Initialize {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
+            mint: test_harness.mint,
+            initializer_deposit_token_account: test_harness.initializer_token_account,
+            escrow_state: second_escrow,
+            vault: second_vault,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Initialize {
+            amount: 40,
+            timeout: 100,
+            terms_hash: [0; 32],
+            external_id: order_id,
+            invoice_hash: [0; 32],
+            order_seeded: true,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: 100,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+    let first_accept_ix = test_harness.accept_arbiter_ix();
+    let second_accept_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::AcceptArbiterRole {
+            arbiter: test_harness.arbiter.pubkey(),
+            escrow_state: second_escrow,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::AcceptArbiterRole {}.data(),
+    };
+    let first_dispute_ix = test_harness.raise_dispute_ix();
+    let second_dispute_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::RaiseDispute {
+            party: test_harness.initializer.pubkey(),
+            escrow_state: second_escrow,
+            party_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::RaiseDispute { reason_code: 2 }.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[first_init_ix, second_init_ix, first_accept_ix, second_accept_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+    process(
+        &mut test_harness.context,
+        &[first_dispute_ix, second_dispute_ix],
+        &[&test_harness.recipient, &test_harness.initializer],
+    )
+    .await
+    .unwrap();
+
+    let mut accounts = escrow::accounts::ResolveBatch {
+        arbiter: test_harness.arbiter.pubkey(),
+    }
+    .to_account_metas(None);
+    for (escrow_state, vault) in [(first_escrow, first_vault), (second_escrow, second_vault)] {
+        accounts.extend(
+            escrow::accounts::ResolveByArbiter {
+                arbiter: test_harness.arbiter.pubkey(),
+                escrow_state,
+                vault,
+                mint: test_harness.mint,
+                mint_stats: test_harness.mint_stats_address(),
+                recipient_deposit_token_account: test_harness.recipient_token_account,
+                initializer_refund_token_account: test_harness.initializer_token_account,
+                token_program: token::ID,
+                instructions_sysvar: None,
+            }
+            .to_account_metas(None),
+        );
+    }
+    let resolve_batch_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts,
+        data: escrow::instruction::ResolveBatch {
+            resolutions: vec![
+                escrow::BatchResolution {
+                    escrow: first_escrow,
+                    release_to_recipient: true,
+                    rationale_hash: [1; 32],
+                },
+                escrow::BatchResolution {
+                    escrow: second_escrow,
+                    release_to_recipient: false,
+                    rationale_hash: [2; 32],
+                },
+            ],
+        }
+        .data(),
+    };
+    process(&mut test_harness.context, &[resolve_batch_ix], &[&test_harness.arbiter])
+        .await
+        .unwrap();
+
+    let first = test_harness.get_account::<escrow::Escrow>(&first_escrow).await.unwrap();
+    assert_eq!(first.status, escrow::EscrowStatus::Withdrawn);
+    assert_eq!(first.rationale_hash, [1; 32]);
+    let second = test_harness.get_account::<escrow::Escrow>(&second_escrow).await.unwrap();
+    assert_eq!(second.status, escrow::EscrowStatus::Refunded);
+    assert_eq!(second.rationale_hash, [2; 32]);
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        40
+    );
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.initializer_token_account)
+            .await,
+        60
+    );
+    let mint_stats = test_harness
+        .get_account::<escrow::MintStats>(&test_harness.mint_stats_address())
+        .await
+        .unwrap();
+    assert_eq!(mint_stats.total_locked, 0);
+}
+
+#[tokio::test]
+async fn test_initialize_records_terms_hash() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let terms_hash = [7; 32];
+
+    let init_ix = test_harness.initialize_ix(50, 100, terms_hash);
+    process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.terms_hash, terms_hash);
+    assert_eq!(escrow_account.mint, test_harness.mint);
+    // The test mint is created without a freeze authority.
+    assert!(!escrow_account.mint_freezable);
+}
+
+#[tokio::test]
+async fn test_create_then_fund_escrow() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let create_ix = test_harness.create_escrow_ix(50, 100, 100, false, 50);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(
+        &mut test_harness.context,
+        &[create_ix, accept_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Funding);
+    assert_eq!(escrow_account.timeout, 0);
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 0);
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.initializer_token_account)
+            .await,
+        100
+    );
+
+    let fund_ix = test_harness.fund_escrow_ix(50);
+    process(&mut test_harness.context, &[fund_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Initialized);
+    assert!(escrow_account.timeout > 0);
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 50);
+}
+
+#[tokio::test]
+async fn test_cancel_unfunded_escrow() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+
+    let create_ix = test_harness.create_escrow_ix(50, 100, 100, false, 50);
+    let cancel_ix = test_harness.cancel_ix();
+    process(
+        &mut test_harness.context,
+        &[create_ix, cancel_ix],
+        &[&test_harness.initializer],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Cancelled);
+
+    // A cancelled escrow can no longer be funded.
+    let fund_ix = test_harness.fund_escrow_ix(50);
+    assert!(
+        process(&mut test_harness.context, &[fund_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_incremental_funding() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let create_ix = test_harness.create_escrow_ix(50, 100, 100, false, 50);
+    let first_fund_ix = test_harness.fund_escrow_ix(20);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(
+        &mut test_harness.context,
+        &[create_ix, accept_ix, first_fund_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Funding);
+    assert_eq!(escrow_account.funded_amount, 20);
+
+    // Depositing more than the remaining 30 tokens is rejected.
+    let over_fund_ix = test_harness.fund_escrow_ix(31);
+    assert!(
+        process(&mut test_harness.context, &[over_fund_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    let second_fund_ix = test_harness.fund_escrow_ix(30);
+    process(&mut test_harness.context, &[second_fund_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Initialized);
+    assert_eq!(escrow_account.funded_amount, 50);
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 50);
+}
+
+#[tokio::test]
+async fn test_refund_underfunded_escrow_after_funding_deadline() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let create_ix = test_harness.create_escrow_ix(50, 100, 1, false, 50);
+    let fund_ix = test_harness.fund_escrow_ix(20);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(
+        &mut test_harness.context,
+        &[create_ix, accept_ix, fund_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    test_harness.advance_clock(2).await;
+
+    let refund_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Refund {
+            initializer: test_harness.initializer.pubkey(),
+            initializer_refund_token_account: test_harness.initializer_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Refund {}.data(),
+    };
+    process(&mut test_harness.context, &[refund_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.initializer_token_account)
+            .await,
+        100
+    );
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Refunded);
+}
+
+#[tokio::test]
+async fn test_crowdfunded_escrow_activates_when_fully_funded() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let backer = Keypair::new();
+    TestContext::airdrop(&mut test_harness.context, &backer.pubkey(), 1_000_000_000).await;
+    let backer_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &test_harness.mint,
+        &backer.pubkey(),
+        &test_harness.mint_authority,
+        100,
+    )
+    .await;
+
+    let create_ix = test_harness.create_escrow_ix(50, 100, 100, true, 50);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(
+        &mut test_harness.context,
+        &[create_ix, accept_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    // The initializer cannot bypass the contribution receipts.
+    let fund_ix = test_harness.fund_escrow_ix(50);
+    assert!(
+        process(&mut test_harness.context, &[fund_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    let initializer_ix = test_harness.contribute_ix(
+        &test_harness.initializer.pubkey(),
+        &test_harness.initializer_token_account,
+        20,
+    );
+    process(&mut test_harness.context, &[initializer_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+    let backer_ix = test_harness.contribute_ix(&backer.pubkey(), &backer_token_account, 30);
+    process(&mut test_harness.context, &[backer_ix], &[&backer])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Initialized);
+    assert_eq!(escrow_account.funded_amount, 50);
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 50);
+
+    let (backer_contribution_pda, _) = Pubkey::find_program_address(
+        &[b"contribution", escrow_state_pda.as_ref(), backer.pubkey().as_ref()],
+        &test_harness.program_id,
+    );
+    let contribution = test_harness
+        .get_account::<escrow::Contribution>(&backer_contribution_pda)
+        .await
+        .unwrap();
+    assert_eq!(contribution.amount, 30);
+    assert_eq!(contribution.contributor, backer.pubkey());
+    assert!(contribution.first_contributed_at > 0);
+    assert!(!contribution.claimed);
+}
+
+#[tokio::test]
+async fn test_cancelled_crowdfund_refunds_each_contributor() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let backer = Keypair::new();
+    TestContext::airdrop(&mut test_harness.context, &backer.pubkey(), 1_000_000_000).await;
+    let backer_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &test_harness.mint,
+        &backer.pubkey(),
+        &test_harness.mint_authority,
+        100,
+    )
+    .await;
+
+    let create_ix = test_harness.create_escrow_ix(50, 100, 100, true, 50);
+    let initializer_ix = test_harness.contribute_ix(
+        &test_harness.initializer.pubkey(),
+        &test_harness.initializer_token_account,
+        10,
+    );
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(
+        &mut test_harness.context,
+        &[create_ix, accept_ix, initializer_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+    let backer_ix = test_harness.contribute_ix(&backer.pubkey(), &backer_token_account, 25);
+    process(&mut test_harness.context, &[backer_ix], &[&backer])
+        .await
+        .unwrap();
+
+    // Cancelling does not sweep the backer's tokens to the initializer.
+    let cancel_ix = test_harness.cancel_ix();
+    process(&mut test_harness.context, &[cancel_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 35);
+
+    let claim_ix = test_harness.claim_contribution_ix(&backer.pubkey(), &backer_token_account);
+    process(&mut test_harness.context, &[claim_ix], &[&backer])
+        .await
+        .unwrap();
+    assert_eq!(test_harness.get_token_balance(&backer_token_account).await, 100);
+
+    // The receipt is kept as proof of contribution and can only be claimed once.
+    let (backer_contribution_pda, _) = Pubkey::find_program_address(
+        &[b"contribution", escrow_state_pda.as_ref(), backer.pubkey().as_ref()],
+        &test_harness.program_id,
+    );
+    let contribution = test_harness
+        .get_account::<escrow::Contribution>(&backer_contribution_pda)
+        .await
+        .unwrap();
+    assert!(contribution.claimed);
+    assert_eq!(contribution.amount, 25);
+    test_harness.refresh_blockhash().await;
+    let claim_again_ix = test_harness.claim_contribution_ix(&backer.pubkey(), &backer_token_account);
+    assert!(
+        process(&mut test_harness.context, &[claim_again_ix], &[&backer])
+            .await
+            .is_err()
+    );
+
+    let claim_ix = test_harness.claim_contribution_ix(
+        &test_harness.initializer.pubkey(),
+        &test_harness.initializer_token_account,
+    );
+    process(&mut test_harness.context, &[claim_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.initializer_token_account)
+            .await,
+        100
+    );
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 0);
+}
+
+#[tokio::test]
+async fn test_crowdfund_activates_at_deadline_once_goal_is_met() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let activate_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::ActivateEscrow {
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::ActivateEscrow {}.data(),
+    };
+
+    // Goal of 30 out of a 50 token target, with a one second funding period.
+    let create_ix = test_harness.create_escrow_ix(50, 100, 1, true, 30);
+    let contribute_ix = test_harness.contribute_ix(
+        &test_harness.initializer.pubkey(),
+        &test_harness.initializer_token_account,
+        35,
+    );
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(
+        &mut test_harness.context,
+        &[create_ix, accept_ix, contribute_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    // The recipient has no claim while the escrow is still funding.
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Funding);
+    assert_eq!(escrow_account.timeout, 0);
+
+    test_harness.advance_clock(2).await;
+
+    process(&mut test_harness.context, &[activate_ix], &[])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Initialized);
+    assert_eq!(escrow_account.amount, 35);
+    assert!(escrow_account.timeout > 0);
+
+    // The goal was met, so contributions are locked in.
+    let claim_ix = test_harness.claim_contribution_ix(
+        &test_harness.initializer.pubkey(),
+        &test_harness.initializer_token_account,
+    );
+    assert!(
+        process(&mut test_harness.context, &[claim_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_activation_needs_milestones_covered_and_no_trade() {
+    fn activate_ix(test_harness: &TestContext) -> Instruction {
+        let (escrow_state_pda, _) = test_harness.escrow_addresses();
+        Instruction {
+            program_id: test_harness.program_id,
+            accounts: escrow::accounts::ActivateEscrow {
+                escrow_state: escrow_state_pda,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::ActivateEscrow {}.data(),
+        }
+    }
+
+    // Goal of 20 out of 50, but a 40 token milestone promised up front.
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let create_ix = test_harness.create_escrow_ix(50, 100, 1, false, 20);
+    let add_ix = test_harness.add_milestone_ix(0, 40);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    let fund_ix = test_harness.fund_escrow_ix(30);
+    process(
+        &mut test_harness.context,
+        &[create_ix, add_ix, accept_ix, fund_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+    test_harness.advance_clock(2).await;
+
+    // 30 meets the goal but not the milestone.
+    let ix = activate_ix(&test_harness);
+    assert!(process(&mut test_harness.context, &[ix], &[]).await.is_err());
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Funding);
+
+    // A trade priced for the full amount also blocks activation.
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let (trade_pda, _) = Pubkey::find_program_address(
+        &[b"trade", escrow_state_pda.as_ref()],
+        &test_harness.program_id,
+    );
+    let open_trade_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::OpenTrade {
+            initializer: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            taker_mint: test_harness.mint,
+            trade: trade_pda,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::OpenTrade { taker_amount: 30 }.data(),
+    };
+    let create_ix = test_harness.create_escrow_ix(50, 100, 1, false, 20);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    let fund_ix = test_harness.fund_escrow_ix(30);
+    process(
+        &mut test_harness.context,
+        &[create_ix, open_trade_ix, accept_ix, fund_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+    test_harness.advance_clock(2).await;
+
+    let ix = activate_ix(&test_harness);
+    assert!(process(&mut test_harness.context, &[ix], &[]).await.is_err());
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Funding);
+}
+
+#[tokio::test]
+async fn test_crowdfund_below_goal_is_refundable() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+
+    let create_ix = test_harness.create_escrow_ix(50, 100, 1, true, 30);
+    let contribute_ix = test_harness.contribute_ix(
+        &test_harness.initializer.pubkey(),
+        &test_harness.initializer_token_account,
+        20,
+    );
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(
+        &mut test_harness.context,
+        &[create_ix, accept_ix, contribute_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    test_harness.advance_clock(2).await;
+
+    let claim_ix = test_harness.claim_contribution_ix(
+        &test_harness.initializer.pubkey(),
+        &test_harness.initializer_token_account,
+    );
+    process(&mut test_harness.context, &[claim_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.initializer_token_account)
+            .await,
+        100
+    );
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Refunded);
+}
+
+#[tokio::test]
+async fn test_withdraw_gated_by_governance_proposal() {
+    use solana_sdk::account::{Account as SolanaAccount, AccountSharedData};
+
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let governance_program = Pubkey::new_unique();
+    let proposal = Pubkey::new_unique();
+
+    // Writes a minimal ProposalV2 account in the given `ProposalState`.
+    let set_proposal_state = |context: &mut ProgramTestContext, state: u8| {
+        let mut data = vec![0u8; 128];
+        data[0] = escrow::GOVERNANCE_PROPOSAL_V2;
+        data[65] = state;
+        context.set_account(
+            &proposal,
+            &AccountSharedData::from(SolanaAccount {
+                lamports: 1_000_000_000,
+                data,
+                owner: governance_program,
+                executable: false,
+                rent_epoch: 0,
+            }),
+        );
+    };
+    set_proposal_state(&mut test_harness.context, 2); // Voting
+
+    // Stand-ins for the governance deployment owning the proposal and an
+    // unrelated program.
+    let other_program = Pubkey::new_unique();
+    for program in [governance_program, other_program] {
+        test_harness.context.set_account(
+            &program,
+            &AccountSharedData::from(SolanaAccount {
+                lamports: 1_000_000_000,
+                data: vec![],
+                owner: solana_sdk::bpf_loader::id(),
+                executable: true,
+                rent_epoch: 0,
+            }),
+        );
+    }
+
+    let mint_stats = test_harness.mint_stats_address();
+    let create_ix = |governance_program: Pubkey| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::CreateEscrow {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
+            mint: test_harness.mint,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint_stats,
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            governance_proposal: Some(proposal),
+            withdraw_cosigner: None,
+            governance_program: Some(governance_program),
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::CreateEscrow {
+            amount: 50,
+            timeout: 100,
+            terms_hash: [0; 32],
+            funding_period: 100,
+            crowdfunded: false,
+            min_goal: 50,
+            retainage_bps: 0,
+            warranty_period: 0,
+            external_id: 0,
+            invoice_hash: [0; 32],
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: 100,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+
+    // The proposal must be owned by the named governance program.
+    assert!(
+        process(
+            &mut test_harness.context,
+            &[create_ix(other_program)],
+            &[&test_harness.initializer],
+        )
+        .await
+        .is_err()
+    );
+
+    let fund_ix = test_harness.fund_escrow_ix(50);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(
+        &mut test_harness.context,
+        &[create_ix(governance_program), accept_ix, fund_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.governance_proposal, proposal);
+    assert_eq!(escrow_account.governance_program, governance_program);
+
+    // Withdrawing without the proposal, or while it is still being voted on, fails.
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    assert!(
+        process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+    let withdraw_ix = test_harness.withdraw_ix(Some(proposal));
+    assert!(
+        process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+
+    set_proposal_state(&mut test_harness.context, escrow::PROPOSAL_STATE_SUCCEEDED);
+    test_harness.refresh_blockhash().await;
+    let withdraw_ix = test_harness.withdraw_ix(Some(proposal));
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        50
+    );
+}
+
+#[tokio::test]
+async fn test_governance_treasury_escrow_refunds_to_treasury() {
+    let mut test_harness = TestContext::new().await;
+    let governance = Pubkey::new_unique();
+    let (treasury, _) = Pubkey::find_program_address(
+        &[b"native-treasury", governance.as_ref()],
+        &GOVERNANCE_STUB_ID,
+    );
+    let treasury_token_account = get_associated_token_address(&treasury, &test_harness.mint);
+    let mint_stats = test_harness.mint_stats_address();
+
+    // The treasury pays the escrow's rent and funds it from its ATA.
+    TestContext::airdrop(&mut test_harness.context, &treasury, 1_000_000_000).await;
+    let fund_treasury_tx = Transaction::new_signed_with_payer(
+        &[
+            associated_token::spl_associated_token_account::instruction::create_associated_token_account(
+                &test_harness.context.payer.pubkey(),
+                &treasury,
+                &test_harness.mint,
+                &spl_token::id(),
+            ),
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                &test_harness.mint,
+                &treasury_token_account,
+                &test_harness.mint_authority.pubkey(),
+                &[],
+                100,
+            )
+            .unwrap(),
+        ],
+        Some(&test_harness.context.payer.pubkey()),
+        &[&test_harness.context.payer, &test_harness.mint_authority],
+        test_harness.context.last_blockhash,
+    );
+    test_harness
+        .context
+        .banks_client
+        .process_transaction(fund_treasury_tx)
+        .await
+        .unwrap();
+
+    let escrow_address = |external_id: u64| {
+        Pubkey::find_program_address(
+            &[b"escrow", treasury.as_ref(), external_id.to_le_bytes().as_ref()],
+            &escrow::id(),
+        )
+        .0
+    };
+    let initialize_ix = |external_id: u64| {
+        let escrow_state = escrow_address(external_id);
+        Instruction {
+            program_id: escrow::id(),
+            accounts: escrow::accounts::Initialize {
+                initializer: treasury,
+                recipient: test_harness.recipient.pubkey(),
+                arbiter: Some(test_harness.arbiter.pubkey()),
+                mint: test_harness.mint,
+                initializer_deposit_token_account: treasury_token_account,
+                escrow_state,
+                vault: get_associated_token_address(&escrow_state, &test_harness.mint),
+                mint_stats,
+                system_program: system_program::ID,
+                token_program: token::ID,
+                associated_token_program: associated_token::ID,
+                withdraw_cosigner: None,
+                recovery_authority: None,
+                recovery_token_account: None,
+                rent_collector: None,
+                arbiter_registrar: None,
+                template: None,
+                initializer_profile: None,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::Initialize {
+                amount: 50,
+                timeout: 100,
+                terms_hash: [0; 32],
+                external_id,
+                invoice_hash: [0; 32],
+                order_seeded: true,
+                release_not_before: 0,
+                reject_freezable: false,
+                timeout_warning_window: 100,
+                dispute_window: 0,
+                required_qualifications: 0,
+                max_arbiter_fee_bps: 0,
+            }
+            .data(),
+        }
+    };
+    let cancel_ix = |refund_to: Pubkey| {
+        let escrow_state = escrow_address(1);
+        Instruction {
+            program_id: escrow::id(),
+            accounts: escrow::accounts::Cancel {
+                initializer: treasury,
+                initializer_refund_token_account: refund_to,
+                escrow_state,
+                vault: get_associated_token_address(&escrow_state, &test_harness.mint),
+                mint: test_harness.mint,
+                mint_stats,
+                token_program: token::ID,
+                instructions_sysvar: None,
+            }
+            .to_account_metas(None),
+            data: escrow::instruction::Cancel {}.data(),
+        }
+    };
+    let escrow_state = escrow_address(2);
+    let refund_ix = Instruction {
+        program_id: escrow::id(),
+        accounts: escrow::accounts::Refund {
+            initializer: treasury,
+            initializer_refund_token_account: treasury_token_account,
+            escrow_state,
+            vault: get_associated_token_address(&escrow_state, &test_harness.mint),
+            mint: test_harness.mint,
+            mint_stats,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Refund {}.data(),
+    };
+    let open_ixs = [
+        via_governance(governance, initialize_ix(1)),
+        via_governance(governance, initialize_ix(2)),
+    ];
+    let mut unsigned_cancel_ix = cancel_ix(treasury_token_account);
+    unsigned_cancel_ix.accounts[0].is_signer = false;
+    let misrouted_cancel_ix = via_governance(governance, cancel_ix(test_harness.recipient_token_account));
+    let governed_cancel_ix = via_governance(governance, cancel_ix(treasury_token_account));
+
+    process(&mut test_harness.context, &open_ixs, &[]).await.unwrap();
+    assert_eq!(test_harness.get_token_balance(&treasury_token_account).await, 0);
+    let escrow_account = test_harness
+        .get_account::<escrow::Escrow>(&escrow_address(1))
+        .await
+        .unwrap();
+    assert_eq!(escrow_account.initializer, treasury);
+
+    // Nobody holds a key for the treasury, so only the governance program
+    // can act for it...
+    assert!(
+        process(&mut test_harness.context, &[unsigned_cancel_ix], &[])
+            .await
+            .is_err()
+    );
+    // ...and even then the funds can only go back to the treasury.
+    assert!(
+        process(&mut test_harness.context, &[misrouted_cancel_ix], &[])
+            .await
+            .is_err()
+    );
+
+    process(&mut test_harness.context, &[governed_cancel_ix], &[])
+        .await
+        .unwrap();
+    assert_eq!(test_harness.get_token_balance(&treasury_token_account).await, 50);
+
+    test_harness.advance_clock(101).await;
+    process(&mut test_harness.context, &[via_governance(governance, refund_ix)], &[])
+        .await
+        .unwrap();
+    assert_eq!(test_harness.get_token_balance(&treasury_token_account).await, 100);
+    let escrow_account = test_harness
+        .get_account::<escrow::Escrow>(&escrow_address(2))
+        .await
+        .unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Refunded);
+}
+
+#[tokio::test]
+async fn test_funding_waits_for_arbiter_acceptance() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let create_ix = test_harness.create_escrow_ix(50, 100, 100, false, 50);
+    process(&mut test_harness.context, &[create_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert!(!escrow_account.arbiter_accepted);
+    assert!(escrow_account.arbiter_acceptance_deadline > 0);
+
+    let fund_ix = test_harness.fund_escrow_ix(50);
+    assert!(
+        process(&mut test_harness.context, &[fund_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(&mut test_harness.context, &[accept_ix], &[&test_harness.arbiter])
+        .await
+        .unwrap();
+    test_harness.refresh_blockhash().await;
+    let fund_ix = test_harness.fund_escrow_ix(50);
+    process(&mut test_harness.context, &[fund_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert!(escrow_account.arbiter_accepted);
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Initialized);
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 50);
+}
+
+#[tokio::test]
+async fn test_parties_cannot_replace_arbiter_before_deadline() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let new_arbiter = Keypair::new();
+
+    let create_ix = test_harness.create_escrow_ix(50, 100, 100, false, 50);
+    process(&mut test_harness.context, &[create_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let appoint_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::AppointArbiter {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            new_arbiter: new_arbiter.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::AppointArbiter {}.data(),
+    };
+    let result = process(
+        &mut test_harness.context,
+        &[appoint_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await;
+    assert!(result.is_err());
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.arbiter, test_harness.arbiter.pubkey());
+}
+
+#[tokio::test]
+async fn test_registered_arbiter_claims_listed_case() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let registrar = test_harness.context.payer.pubkey();
+    let unqualified_arbiter = Keypair::new();
+    let registration_address = |arbiter: Pubkey| {
+        Pubkey::find_program_address(
+            &[b"arbiter", registrar.as_ref(), arbiter.as_ref()],
+            &escrow::id(),
+        )
+        .0
+    };
+    let register_ix = |arbiter: Pubkey, qualifications: u32, fee_bps: u16| Instruction {
+        program_id: escrow::id(),
+        accounts: escrow::accounts::RegisterArbiter {
+            registrar,
+            arbiter,
+            registration: registration_address(arbiter),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::RegisterArbiter {
+            qualifications,
+            fee_bps,
+        }
+        .data(),
+    };
+    let claim_ix = |arbiter: Pubkey| Instruction {
+        program_id: escrow::id(),
+        accounts: escrow::accounts::ClaimCase {
+            arbiter,
+            registration: registration_address(arbiter),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::ClaimCase {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[
+            register_ix(test_harness.arbiter.pubkey(), 0b11, 200),
+            register_ix(unqualified_arbiter.pubkey(), 0b01, 100),
+        ],
+        &[&test_harness.arbiter, &unqualified_arbiter],
+    )
+    .await
+    .unwrap();
+
+    // The escrow names no arbiter and lists the case instead.
+    let (_, vault_pda) = test_harness.escrow_addresses();
+    let listing_ix = |arbiter_registrar: Option<Pubkey>| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::CreateEscrow {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: None,
+            mint: test_harness.mint,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            governance_proposal: None,
+            withdraw_cosigner: None,
+            governance_program: None,
+            rent_collector: None,
+            arbiter_registrar,
+            template: None,
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::CreateEscrow {
+            amount: 50,
+            timeout: 100,
+            terms_hash: [0; 32],
+            funding_period: 100,
+            crowdfunded: false,
+            min_goal: 50,
+            retainage_bps: 0,
+            warranty_period: 0,
+            external_id: 0,
+            invoice_hash: [0; 32],
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: 100,
+            dispute_window: 0,
+            required_qualifications: 0b10,
+            max_arbiter_fee_bps: 300,
+        }
+        .data(),
+    };
+    let unclaimable_ix = listing_ix(None);
+    let listed_ix = listing_ix(Some(registrar));
+    // Without a registrar, no one could ever claim it.
+    assert!(
+        process(&mut test_harness.context, &[unclaimable_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+    process(&mut test_harness.context, &[listed_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.arbiter, Pubkey::default());
+    assert_eq!(escrow_account.arbiter_registrar, registrar);
+
+    // An arbiter missing a required qualification cannot claim it.
+    assert!(
+        process(
+            &mut test_harness.context,
+            &[claim_ix(unqualified_arbiter.pubkey())],
+            &[&unqualified_arbiter],
+        )
+        .await
+        .is_err()
+    );
+
+    process(
+        &mut test_harness.context,
+        &[claim_ix(test_harness.arbiter.pubkey())],
+        &[&test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.arbiter, test_harness.arbiter.pubkey());
+    assert!(escrow_account.arbiter_accepted);
+    assert_eq!(escrow_account.arbiter_fee_bps, 200);
+
+    // The first claim takes the case.
+    test_harness.refresh_blockhash().await;
+    assert!(
+        process(
+            &mut test_harness.context,
+            &[claim_ix(test_harness.arbiter.pubkey())],
+            &[&test_harness.arbiter],
+        )
+        .await
+        .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_resigned_arbiter_is_replaced_by_the_parties() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let new_arbiter = Keypair::new();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    let resign_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::ResignArbiter {
+            arbiter: test_harness.arbiter.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::ResignArbiter {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, accept_ix, resign_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert!(!escrow_account.arbiter_accepted);
+    assert!(escrow_account.replacement_deadline > 0);
+
+    // With no backup arbiter named, only the parties can fill the seat.
+    let appoint_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::AppointArbiter {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            new_arbiter: new_arbiter.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::AppointArbiter {}.data(),
+    };
+    let accept_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::AcceptArbiterRole {
+            arbiter: new_arbiter.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::AcceptArbiterRole {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[appoint_ix, accept_ix],
+        &[&test_harness.initializer, &test_harness.recipient, &new_arbiter],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.arbiter, new_arbiter.pubkey());
+    assert!(escrow_account.arbiter_accepted);
+    assert_eq!(escrow_account.replacement_deadline, 0);
+}
+
+#[tokio::test]
+async fn test_backup_arbiter_resolves_after_resignation_in_dispute() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let backup = Keypair::new();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    let dispute_ix = test_harness.raise_dispute_ix();
+    process(
+        &mut test_harness.context,
+        &[init_ix, accept_ix, dispute_ix],
+        &[&test_harness.initializer, &test_harness.arbiter, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    // Without a backup arbiter, resigning would leave the dispute with no one
+    // able to resolve it.
+    let resign_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::ResignArbiter {
+            arbiter: test_harness.arbiter.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::ResignArbiter {}.data(),
+    };
+    assert!(
+        process(&mut test_harness.context, std::slice::from_ref(&resign_ix), &[&test_harness.arbiter])
+            .await
+            .is_err()
+    );
+
+    let set_backup_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::SetBackupArbiter {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            backup_arbiter: backup.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::SetBackupArbiter {}.data(),
+    };
+    test_harness.refresh_blockhash().await;
+    process(
+        &mut test_harness.context,
+        &[set_backup_ix, resign_ix],
+        &[&test_harness.initializer, &test_harness.recipient, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    // Once the replacement window closes, the backup takes over and settles.
+    test_harness.advance_clock(escrow::ARBITER_REPLACEMENT_PERIOD).await;
+    let assume_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::AssumeBackupArbiter {
+            backup_arbiter: backup.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::AssumeBackupArbiter {}.data(),
+    };
+    let resolve_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::ResolveByArbiter {
+            arbiter: backup.pubkey(),
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            initializer_refund_token_account: test_harness.initializer_token_account,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::ResolveByArbiter {
+            release_to_recipient: false,
+            rationale_hash: [3; 32],
+        }
+        .data(),
+    };
+    process(&mut test_harness.context, &[assume_ix, resolve_ix], &[&backup])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.arbiter, backup.pubkey());
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Refunded);
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.initializer_token_account)
+            .await,
+        100
+    );
+}
+
+#[tokio::test]
+async fn test_withdraw_holds_back_retainage_until_sign_off() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let create_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::CreateEscrow {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
+            mint: test_harness.mint,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            governance_proposal: None,
+            withdraw_cosigner: None,
+            governance_program: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::CreateEscrow {
+            amount: 50,
+            timeout: 100,
+            terms_hash: [0; 32],
+            funding_period: 100,
+            crowdfunded: false,
+            min_goal: 50,
+            retainage_bps: 1_000,
+            warranty_period: 100,
+            external_id: 0,
+            invoice_hash: [0; 32],
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: 100,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+    let accept_ix = test_harness.accept_arbiter_ix();
+    let fund_ix = test_harness.fund_escrow_ix(50);
+    process(
+        &mut test_harness.context,
+        &[create_ix, accept_ix, fund_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+
+    // 10% of the 50 tokens stays in the vault.
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        45
+    );
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 5);
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Withdrawn);
+    assert_eq!(escrow_account.retained_amount, 5);
+
+    let release_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::ReleaseRetainage {
+            initializer: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::ReleaseRetainage {}.data(),
+    };
+    process(&mut test_harness.context, &[release_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        50
+    );
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.retained_amount, 0);
+}
+
+#[tokio::test]
+async fn test_migrate_escrow_grows_old_accounts() {
+    use solana_sdk::account::{AccountSharedData, ReadableAccount, WritableAccount};
+
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    // Shrink the account back to the size it had before `Escrow` last grew.
+    const PREVIOUS_LEN: usize = 790;
+    let account = test_harness
+        .context
+        .banks_client
+        .get_account(escrow_state_pda)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut old_account = AccountSharedData::new(
+        account.lamports,
+        8 + PREVIOUS_LEN,
+        account.owner(),
+    );
+    old_account
+        .data_as_mut_slice()
+        .copy_from_slice(&account.data()[..8 + PREVIOUS_LEN]);
+    // Escrows from before arbiters had to accept the role hold zeros where
+    // `arbiter_accepted` and `arbiter_acceptance_deadline` now are.
+    const ARBITER_ACCEPTED_OFFSET: usize = 8 + 1 + 32 * 4 + 8 + 8 + 1 + 1 + 32 + 1 + 8 + 8 + 8 + 1 + 8 + 32 + 32;
+    old_account.data_as_mut_slice()[ARBITER_ACCEPTED_OFFSET..ARBITER_ACCEPTED_OFFSET + 9].fill(0);
+    test_harness.context.set_account(&escrow_state_pda, &old_account);
+
+    let migrate_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::MigrateEscrow {
+            payer: test_harness.context.payer.pubkey(),
+            escrow_state: escrow_state_pda,
+            legacy_vault: None,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::MigrateEscrow {}.data(),
+    };
+    process(&mut test_harness.context, &[migrate_ix], &[])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.amount, 50);
+    assert_eq!(escrow_account.retained_amount, 0);
+    assert!(!escrow_account.arbiter_accepted);
+    assert!(escrow_account.arbiter_acceptance_deadline > 0);
+
+    // The arbiter can still take up the role after the migration.
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(&mut test_harness.context, &[accept_ix], &[&test_harness.arbiter])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_migrate_original_layout_escrow_and_vault() {
+    use solana_sdk::account::{AccountSharedData, WritableAccount};
+
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, escrow_bump) = Pubkey::find_program_address(
+        &[
+            b"escrow",
+            test_harness.initializer.pubkey().as_ref(),
+            test_harness.recipient.pubkey().as_ref(),
+        ],
+        &test_harness.program_id,
+    );
+    let (legacy_vault_pda, legacy_vault_bump) = Pubkey::find_program_address(
+        &[b"vault", escrow_state_pda.as_ref()],
+        &test_harness.program_id,
+    );
+    let vault_pda = get_associated_token_address(&escrow_state_pda, &test_harness.mint);
+    let rent = test_harness.context.banks_client.get_rent().await.unwrap();
+    let clock: Clock = test_harness.context.banks_client.get_sysvar().await.unwrap();
+    let timeout = clock.unix_timestamp + 1_000;
+
+    // The escrow as the first release wrote it: initializer, recipient,
+    // arbiter, amount, timeout, status, vault bump and escrow bump, with no
+    // mint and 115 bytes after the discriminator.
+    let mut data = escrow::Escrow::DISCRIMINATOR.to_vec();
+    data.extend_from_slice(test_harness.initializer.pubkey().as_ref());
+    data.extend_from_slice(test_harness.recipient.pubkey().as_ref());
+    data.extend_from_slice(test_harness.arbiter.pubkey().as_ref());
+    data.extend_from_slice(&50u64.to_le_bytes());
+    data.extend_from_slice(&timeout.to_le_bytes());
+    data.push(0);
+    data.push(legacy_vault_bump);
+    data.push(escrow_bump);
+    assert_eq!(data.len(), 8 + 115);
+    let mut old_escrow = AccountSharedData::new(
+        rent.minimum_balance(data.len()),
+        data.len(),
+        &test_harness.program_id,
+    );
+    old_escrow.data_as_mut_slice().copy_from_slice(&data);
+    test_harness.context.set_account(&escrow_state_pda, &old_escrow);
+
+    // Its funds sit in a token account at the `b"vault"` PDA, owned by itself.
+    let mut legacy_vault_data = vec![0; spl_token::state::Account::LEN];
+    spl_token::state::Account {
+        mint: test_harness.mint,
+        owner: legacy_vault_pda,
+        amount: 50,
+        state: spl_token::state::AccountState::Initialized,
+        ..Default::default()
+    }
+    .pack_into_slice(&mut legacy_vault_data);
+    let mut legacy_vault = AccountSharedData::new(
+        rent.minimum_balance(legacy_vault_data.len()),
+        legacy_vault_data.len(),
+        &spl_token::id(),
+    );
+    legacy_vault.data_as_mut_slice().copy_from_slice(&legacy_vault_data);
+    test_harness.context.set_account(&legacy_vault_pda, &legacy_vault);
+
+    let payer = test_harness.context.payer.pubkey();
+    let migrate_escrow_ix = |legacy_vault: Option<Pubkey>| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::MigrateEscrow {
+            payer,
+            escrow_state: escrow_state_pda,
+            legacy_vault,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::MigrateEscrow {}.data(),
+    };
+    // The mint can only come from the legacy vault.
+    assert!(process(&mut test_harness.context, &[migrate_escrow_ix(None)], &[])
+        .await
+        .is_err());
+
+    let migrate_vault_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::MigrateVault {
+            payer: test_harness.context.payer.pubkey(),
+            rent_collector: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            mint: test_harness.mint,
+            legacy_vault: legacy_vault_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::MigrateVault {}.data(),
+    };
+    let init_mint_stats_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::InitMintStats {
+            payer: test_harness.context.payer.pubkey(),
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::InitMintStats {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[
+            migrate_escrow_ix(Some(legacy_vault_pda)),
+            migrate_vault_ix,
+            init_mint_stats_ix,
+        ],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Initialized);
+    assert_eq!(escrow_account.mint, test_harness.mint);
+    assert_eq!(escrow_account.initializer, test_harness.initializer.pubkey());
+    assert_eq!(escrow_account.recipient, test_harness.recipient.pubkey());
+    assert_eq!(escrow_account.arbiter, test_harness.arbiter.pubkey());
+    assert_eq!(escrow_account.amount, 50);
+    assert_eq!(escrow_account.timeout, timeout);
+    assert_eq!(escrow_account.escrow_bump, escrow_bump);
+    assert_eq!(escrow_account.vault_bump, 0);
+    assert!(escrow_account.arbiter_acceptance_deadline > 0);
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 50);
+
+    // From here on the escrow settles like any other.
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        50
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_vault_moves_legacy_funds() {
+    use solana_sdk::account::{AccountSharedData, ReadableAccount, WritableAccount};
+
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    // Recreate the pre-ATA layout: the funds sit in a token account at the
+    // `b"vault"` PDA, owned by itself, and the escrow records its bump.
+    let (legacy_vault_pda, legacy_vault_bump) = Pubkey::find_program_address(
+        &[b"vault", escrow_state_pda.as_ref()],
+        &test_harness.program_id,
+    );
+    let vault = test_harness
+        .context
+        .banks_client
+        .get_account(vault_pda)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut legacy_vault = AccountSharedData::new(vault.lamports, vault.data.len(), vault.owner());
+    legacy_vault.data_as_mut_slice().copy_from_slice(vault.data());
+    legacy_vault.data_as_mut_slice()[32..64].copy_from_slice(legacy_vault_pda.as_ref());
+    test_harness.context.set_account(&legacy_vault_pda, &legacy_vault);
+    test_harness.context.set_account(&vault_pda, &AccountSharedData::default());
+
+    let escrow = test_harness
+        .context
+        .banks_client
+        .get_account(escrow_state_pda)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut old_escrow = AccountSharedData::new(escrow.lamports, escrow.data.len(), escrow.owner());
+    old_escrow.data_as_mut_slice().copy_from_slice(escrow.data());
+    // `vault_bump` follows status, four keys, amount and timeout.
+    old_escrow.data_as_mut_slice()[8 + 1 + 32 * 4 + 8 + 8] = legacy_vault_bump;
+    test_harness.context.set_account(&escrow_state_pda, &old_escrow);
+
+    let migrate_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::MigrateVault {
+            payer: test_harness.context.payer.pubkey(),
+            rent_collector: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            mint: test_harness.mint,
+            legacy_vault: legacy_vault_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::MigrateVault {}.data(),
+    };
+    process(&mut test_harness.context, std::slice::from_ref(&migrate_ix), &[])
+        .await
+        .unwrap();
+
+    let vault_account = test_harness.get_account::<token::TokenAccount>(&vault_pda).await.unwrap();
+    assert_eq!(vault_account.amount, 50);
+    assert!(test_harness
+        .context
+        .banks_client
+        .get_account(legacy_vault_pda)
+        .await
+        .unwrap()
+        .is_none());
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.vault_bump, 0);
+
+    // A second migration has nothing left to move.
+    test_harness.refresh_blockhash().await;
+    assert!(process(&mut test_harness.context, &[migrate_ix], &[])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_withdraw_requires_named_cosigner() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let cosigner = Keypair::new();
+
+    let init_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Initialize {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
+            mint: test_harness.mint,
+            initializer_deposit_token_account: test_harness.initializer_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: Some(cosigner.pubkey()),
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Initialize {
+            amount: 50,
+            timeout: 100,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: 100,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+    process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.withdraw_cosigner, cosigner.pubkey());
+
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    assert!(
+        process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+
+    let withdraw_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Withdraw {
+            recipient: test_harness.recipient.pubkey(),
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            token_program: token::ID,
+            governance_proposal: None,
+            withdraw_cosigner: Some(cosigner.pubkey()),
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Withdraw {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[withdraw_ix],
+        &[&test_harness.recipient, &cosigner],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        50
+    );
+}
+
+#[tokio::test]
+async fn test_recovery_key_refunds_long_after_timeout() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let recovery = Keypair::new();
+    let recovery_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &test_harness.mint,
+        &recovery.pubkey(),
+        &test_harness.mint_authority,
+        0,
+    )
+    .await;
+
+    let init_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Initialize {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
+            mint: test_harness.mint,
+            initializer_deposit_token_account: test_harness.initializer_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: Some(recovery.pubkey()),
+            recovery_token_account: Some(recovery_token_account),
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Initialize {
+            amount: 50,
+            timeout: 1,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: 1,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+    process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let recover_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::RecoverRefund {
+            recovery_authority: recovery.pubkey(),
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            recovery_token_account,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::RecoverRefund {}.data(),
+    };
+
+    // Only the initializer may refund right after the timeout.
+    test_harness.advance_clock(2).await;
+    assert!(
+        process(&mut test_harness.context, std::slice::from_ref(&recover_ix), &[&recovery])
+            .await
+            .is_err()
+    );
+
+    let mut clock: Clock = test_harness.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += escrow::RECOVERY_DELAY;
+    test_harness.context.set_sysvar(&clock);
+    // A fresh blockhash keeps the retry from being deduplicated.
+    test_harness.refresh_blockhash().await;
+
+    process(&mut test_harness.context, &[recover_ix], &[&recovery])
+        .await
+        .unwrap();
+
+    assert_eq!(test_harness.get_token_balance(&recovery_token_account).await, 50);
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Refunded);
+}
+
+#[tokio::test]
+async fn test_beneficiary_claims_for_inactive_recipient() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let beneficiary = Keypair::new();
+    let beneficiary_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &test_harness.mint,
+        &beneficiary.pubkey(),
+        &test_harness.mint_authority,
+        0,
+    )
+    .await;
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let register_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::RegisterBeneficiary {
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+            beneficiary_token_account,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::RegisterBeneficiary { window: 1 }.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, register_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    test_harness.advance_clock(2).await;
+
+    let claim_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::ClaimAsBeneficiary {
+            beneficiary: beneficiary.pubkey(),
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            beneficiary_token_account,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::ClaimAsBeneficiary {}.data(),
+    };
+    process(&mut test_harness.context, &[claim_ix], &[&beneficiary])
+        .await
+        .unwrap();
+
+    assert_eq!(test_harness.get_token_balance(&beneficiary_token_account).await, 50);
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Withdrawn);
+}
+
+#[tokio::test]
+async fn test_timeout_warning_is_emitted_once() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let warning_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::EmitTimeoutWarning {
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::EmitTimeoutWarning {}.data(),
+    };
+
+    // The warning opens 10 seconds before the 100 second timeout.
+    let mut init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    init_ix.data = escrow::instruction::Initialize {
+        amount: 50,
+        timeout: 100,
+        terms_hash: [0; 32],
+        external_id: 0,
+        invoice_hash: [0; 32],
+        order_seeded: false,
+        release_not_before: 0,
+        reject_freezable: false,
+        timeout_warning_window: 10,
+        dispute_window: 0,
+        required_qualifications: 0,
+        max_arbiter_fee_bps: 0,
+    }
+    .data();
+    process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+    assert!(
+        process(&mut test_harness.context, std::slice::from_ref(&warning_ix), &[])
+            .await
+            .is_err()
+    );
+
+    test_harness.advance_clock(90).await;
+    process(&mut test_harness.context, std::slice::from_ref(&warning_ix), &[])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert!(escrow_account.timeout_warning_emitted);
+
+    // A fresh blockhash makes sure the retry is rejected by the program, not
+    // deduplicated.
+    test_harness.refresh_blockhash().await;
+    assert!(
+        process(&mut test_harness.context, &[warning_ix], &[])
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_timeout_warning_window_must_fit_the_duration() {
+    let mut test_harness = TestContext::new().await;
+
+    for timeout_warning_window in [0, 101] {
+        let mut init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+        init_ix.data = escrow::instruction::Initialize {
+            amount: 50,
+            timeout: 100,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data();
+        assert!(
+            process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+                .await
+                .is_err()
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_withdraw_waits_for_the_review_window() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let timeout = 10 * escrow::MIN_DISPUTE_WINDOW;
+    let initialize_data = |dispute_window| {
+        escrow::instruction::Initialize {
+            amount: 50,
+            timeout,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: timeout,
+            dispute_window,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data()
+    };
+
+    // Windows outside the program's bounds, or not ending before the
+    // timeout, are rejected.
+    for dispute_window in [escrow::MIN_DISPUTE_WINDOW - 1, escrow::MAX_DISPUTE_WINDOW + 1, timeout] {
+        let mut init_ix = test_harness.initialize_ix(50, timeout, [0; 32]);
+        init_ix.data = initialize_data(dispute_window);
+        assert!(
+            process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+                .await
+                .is_err()
+        );
+    }
+
+    let mut init_ix = test_harness.initialize_ix(50, timeout, [0; 32]);
+    init_ix.data = initialize_data(escrow::MIN_DISPUTE_WINDOW);
+    process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.dispute_window, escrow::MIN_DISPUTE_WINDOW);
+
+    // Nothing can be withdrawn before the work is marked delivered...
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    assert!(
+        process(&mut test_harness.context, std::slice::from_ref(&withdraw_ix), &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+
+    let deliver_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::MarkDelivered {
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::MarkDelivered {}.data(),
+    };
+    process(&mut test_harness.context, &[deliver_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert!(escrow_account.delivered_at > 0);
+
+    // ...nor until the initializer had the whole window to review it.
+    test_harness.refresh_blockhash().await;
+    assert!(
+        process(&mut test_harness.context, std::slice::from_ref(&withdraw_ix), &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+
+    test_harness.advance_clock(escrow::MIN_DISPUTE_WINDOW).await;
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+    assert_eq!(
+        test_harness.get_token_balance(&test_harness.recipient_token_account).await,
+        50
+    );
+}
+
+#[tokio::test]
+async fn test_escrow_takes_template_parameters() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let authority = test_harness.context.payer.pubkey();
+    let template = Pubkey::find_program_address(
+        &[b"template", authority.as_ref(), 7u64.to_le_bytes().as_ref()],
+        &escrow::id(),
+    )
+    .0;
+    let create_template_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::CreateTemplate {
+            authority,
+            template,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::CreateTemplate {
+            template_id: 7,
+            category: escrow::EscrowCategory::Services,
+            dispute_window: escrow::MIN_DISPUTE_WINDOW,
+            retainage_bps: 1_000,
+            warranty_period: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+    process(&mut test_harness.context, &[create_template_ix], &[])
+        .await
+        .unwrap();
+
+    // Only the template's authority can change it.
+    let update_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::UpdateTemplate {
+            authority: test_harness.initializer.pubkey(),
+            template,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::UpdateTemplate {
+            category: escrow::EscrowCategory::Services,
+            dispute_window: 0,
+            retainage_bps: 0,
+            warranty_period: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+    assert!(
+        process(&mut test_harness.context, &[update_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    let timeout = 10 * escrow::MIN_DISPUTE_WINDOW;
+    let init_ix = |dispute_window: i64| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Initialize {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
+            mint: test_harness.mint,
+            initializer_deposit_token_account: test_harness.initializer_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: Some(template),
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Initialize {
+            amount: 50,
+            timeout,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: timeout,
+            dispute_window,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+    let overriding_ix = init_ix(2 * escrow::MIN_DISPUTE_WINDOW);
+    let templated_ix = init_ix(0);
+
+    // The escrow cannot pick its own value for a parameter the template sets.
+    assert!(
+        process(&mut test_harness.context, &[overriding_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+    process(&mut test_harness.context, &[templated_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.template, template);
+    assert_eq!(escrow_account.dispute_window, escrow::MIN_DISPUTE_WINDOW);
+    assert_eq!(escrow_account.retainage_bps, 1_000);
+}
+
+#[tokio::test]
+async fn test_heartbeat_extends_timeout_up_to_max_duration() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let enable_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::EnableHeartbeat {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::EnableHeartbeat {
+            increment: 150,
+            max_duration: 200,
+        }
+        .data(),
+    };
+    let heartbeat_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Heartbeat {
+            party: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Heartbeat {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, enable_ix, heartbeat_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    let activated_at = escrow_account.timeout - escrow_account.duration;
+    assert!(escrow_account.timeout >= activated_at + 150);
+    assert!(escrow_account.timeout <= activated_at + 200);
+}
+
+#[tokio::test]
+async fn test_only_escrow_parties_post_messages() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let outsider = Keypair::new();
+    let post_message_ix = |sender: Pubkey| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::PostMessage {
+            sender,
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::PostMessage {
+            content_hash: [3; 32],
+            recipient_role: escrow::EscrowRole::Arbiter,
+        }
+        .data(),
+    };
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let recipient_ix = post_message_ix(test_harness.recipient.pubkey());
+    let outsider_ix = post_message_ix(outsider.pubkey());
+    process(
+        &mut test_harness.context,
+        &[init_ix, recipient_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    assert!(
+        process(&mut test_harness.context, &[outsider_ix], &[&outsider])
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_mint_stats_track_locked_tokens() {
+    let mut test_harness = TestContext::new().await;
+    let mint_stats_pda = test_harness.mint_stats_address();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let mint_stats = test_harness.get_account::<escrow::MintStats>(&mint_stats_pda).await.unwrap();
+    assert_eq!(mint_stats.mint, test_harness.mint);
+    assert_eq!(mint_stats.active_escrows, 1);
+    assert_eq!(mint_stats.total_locked, 50);
+    assert_eq!(mint_stats.lifetime_volume, 50);
+
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+
+    let mint_stats = test_harness.get_account::<escrow::MintStats>(&mint_stats_pda).await.unwrap();
+    assert_eq!(mint_stats.active_escrows, 0);
+    assert_eq!(mint_stats.total_locked, 0);
+    assert_eq!(mint_stats.lifetime_volume, 50);
+}
+
+#[tokio::test]
+async fn test_order_seeded_escrow_derives_from_order_id() {
+    let mut test_harness = TestContext::new().await;
+    let order_id: u64 = 42;
+    let (escrow_state_pda, _) = Pubkey::find_program_address(
+        &[
+            b"escrow",
+            test_harness.initializer.pubkey().as_ref(),
+            &order_id.to_le_bytes(),
+        ],
+        &test_harness.program_id,
+    );
+    let vault_pda = get_associated_token_address(&escrow_state_pda, &test_harness.mint);
+
+    let init_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Initialize {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
+            mint: test_harness.mint,
+            initializer_deposit_token_account: test_harness.initializer_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Initialize {
+            amount: 50,
+            timeout: 100,
+            terms_hash: [0; 32],
+            external_id: order_id,
+            invoice_hash: [0; 32],
+            order_seeded: true,
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: 100,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+    let withdraw_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Withdraw {
+            recipient: test_harness.recipient.pubkey(),
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            token_program: token::ID,
+            governance_proposal: None,
+            withdraw_cosigner: None,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Withdraw {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, withdraw_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert!(escrow_account.order_seeded);
+    assert_eq!(escrow_account.external_id, order_id);
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Withdrawn);
+}
+
+#[tokio::test]
+async fn test_withdraw_waits_for_release_date() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let mut clock: Clock = test_harness.context.banks_client.get_sysvar().await.unwrap();
+    let release_not_before = clock.unix_timestamp + 50;
+
+    let init_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Initialize {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
+            mint: test_harness.mint,
+            initializer_deposit_token_account: test_harness.initializer_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Initialize {
+            amount: 50,
+            timeout: 100,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before,
+            reject_freezable: false,
+            timeout_warning_window: 100,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+    process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    assert!(
+        process(&mut test_harness.context, std::slice::from_ref(&withdraw_ix), &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+
+    clock.unix_timestamp = release_not_before;
+    test_harness.context.set_sysvar(&clock);
+    test_harness.refresh_blockhash().await;
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.release_not_before, release_not_before);
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Withdrawn);
+}
+
+#[tokio::test]
+async fn test_settlement_guard_rejects_foreign_instructions() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let guard_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::EnableSettlementGuard {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::EnableSettlementGuard {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, guard_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    let withdraw_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Withdraw {
+            recipient: test_harness.recipient.pubkey(),
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            token_program: token::ID,
+            governance_proposal: None,
+            withdraw_cosigner: None,
+            instructions_sysvar: Some(solana_sdk::sysvar::instructions::ID),
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Withdraw {}.data(),
+    };
+
+    // Without the instructions sysvar the guard cannot be checked.
+    let unguarded_ix = test_harness.withdraw_ix(None);
+    assert!(
+        process(&mut test_harness.context, &[unguarded_ix], &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+
+    // Any other program in the same transaction is rejected.
+    let transfer_ix = solana_sdk::system_instruction::transfer(
+        &test_harness.context.payer.pubkey(),
+        &test_harness.recipient.pubkey(),
+        1,
+    );
+    assert!(
+        process(
+            &mut test_harness.context,
+            &[transfer_ix, withdraw_ix.clone()],
+            &[&test_harness.recipient],
+        )
+        .await
+        .is_err()
+    );
+
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_only_the_auditor_requests_snapshots() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let auditor = Keypair::new();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let set_auditor_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::SetAuditor {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            auditor: auditor.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::SetAuditor {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, set_auditor_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.auditor, auditor.pubkey());
+
+    let snapshot_ix = |signer: Pubkey| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::RequestAuditSnapshot {
+            auditor: signer,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::RequestAuditSnapshot {}.data(),
+    };
+    let auditor_ix = snapshot_ix(auditor.pubkey());
+    let arbiter_ix = snapshot_ix(test_harness.arbiter.pubkey());
+
+    process(&mut test_harness.context, &[auditor_ix], &[&auditor])
+        .await
+        .unwrap();
+    assert!(
+        process(&mut test_harness.context, &[arbiter_ix], &[&test_harness.arbiter])
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_top_up_raises_escrow_amount() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let top_up_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::TopUp {
+            initializer: test_harness.initializer.pubkey(),
+            initializer_deposit_token_account: test_harness.initializer_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::TopUp { amount: 20 }.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, top_up_ix],
+        &[&test_harness.initializer],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.amount, 70);
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 70);
+
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        70
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_swaps_deposit_for_payment() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let (trade_pda, _) = Pubkey::find_program_address(
+        &[b"trade", escrow_state_pda.as_ref()],
+        &test_harness.program_id,
+    );
+
+    let taker_authority = Keypair::new();
+    let taker_mint = TestContext::create_mint(&mut test_harness.context, &taker_authority.pubkey()).await;
+    let recipient_payment_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &taker_mint,
+        &test_harness.recipient.pubkey(),
+        &taker_authority,
+        30,
+    )
+    .await;
+    let initializer_receive_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &taker_mint,
+        &test_harness.initializer.pubkey(),
+        &taker_authority,
+        0,
+    )
+    .await;
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let open_trade_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::OpenTrade {
+            initializer: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            taker_mint,
+            trade: trade_pda,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::OpenTrade { taker_amount: 30 }.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, open_trade_ix],
+        &[&test_harness.initializer],
+    )
+    .await
+    .unwrap();
+
+    // The deposit is only released against payment.
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    assert!(
+        process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+
+    let exchange_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Exchange {
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+            trade: trade_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            taker_mint,
+            mint_stats: test_harness.mint_stats_address(),
+            recipient_payment_token_account,
+            initializer_receive_token_account,
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Exchange { amount: 50 }.data(),
+    };
+    process(&mut test_harness.context, &[exchange_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        50
+    );
+    assert_eq!(
+        test_harness
+            .get_token_balance(&initializer_receive_token_account)
+            .await,
+        30
+    );
+    assert_eq!(
+        test_harness
+            .get_token_balance(&recipient_payment_token_account)
+            .await,
+        0
+    );
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Withdrawn);
+}
+
+#[tokio::test]
+async fn test_exchange_honours_settlement_guard() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let (trade_pda, _) = Pubkey::find_program_address(
+        &[b"trade", escrow_state_pda.as_ref()],
+        &test_harness.program_id,
+    );
+
+    let taker_authority = Keypair::new();
+    let taker_mint = TestContext::create_mint(&mut test_harness.context, &taker_authority.pubkey()).await;
+    let recipient_payment_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &taker_mint,
+        &test_harness.recipient.pubkey(),
+        &taker_authority,
+        30,
+    )
+    .await;
+    let initializer_receive_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &taker_mint,
+        &test_harness.initializer.pubkey(),
+        &taker_authority,
+        0,
+    )
+    .await;
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let open_trade_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::OpenTrade {
+            initializer: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            taker_mint,
+            trade: trade_pda,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::OpenTrade { taker_amount: 30 }.data(),
+    };
+    let guard_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::EnableSettlementGuard {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::EnableSettlementGuard {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, open_trade_ix, guard_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    let mint_stats = test_harness.mint_stats_address();
+    let exchange_ix = |instructions_sysvar: Option<Pubkey>| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Exchange {
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+            trade: trade_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            taker_mint,
+            mint_stats,
+            recipient_payment_token_account,
+            initializer_receive_token_account,
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            token_program: token::ID,
+            instructions_sysvar,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Exchange { amount: 50 }.data(),
+    };
+
+    // Without the instructions sysvar the guard cannot be checked.
+    assert!(
+        process(&mut test_harness.context, &[exchange_ix(None)], &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+
+    // Any other program in the same transaction is rejected.
+    let guarded_ix = exchange_ix(Some(solana_sdk::sysvar::instructions::ID));
+    let transfer_ix = solana_sdk::system_instruction::transfer(
+        &test_harness.context.payer.pubkey(),
+        &test_harness.recipient.pubkey(),
+        1,
+    );
+    assert!(
+        process(
+            &mut test_harness.context,
+            &[transfer_ix, guarded_ix.clone()],
+            &[&test_harness.recipient],
+        )
+        .await
+        .is_err()
+    );
+
+    process(&mut test_harness.context, &[guarded_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Withdrawn);
+}
+
+#[tokio::test]
+async fn test_partial_fill_then_cancel_the_rest() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let (trade_pda, _) = Pubkey::find_program_address(
+        &[b"trade", escrow_state_pda.as_ref()],
+        &test_harness.program_id,
+    );
+
+    let taker_authority = Keypair::new();
+    let taker_mint = TestContext::create_mint(&mut test_harness.context, &taker_authority.pubkey()).await;
+    let recipient_payment_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &taker_mint,
+        &test_harness.recipient.pubkey(),
+        &taker_authority,
+        30,
+    )
+    .await;
+    let initializer_receive_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &taker_mint,
+        &test_harness.initializer.pubkey(),
+        &taker_authority,
+        0,
+    )
+    .await;
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let open_trade_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::OpenTrade {
+            initializer: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            taker_mint,
+            trade: trade_pda,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::OpenTrade { taker_amount: 30 }.data(),
+    };
+    let exchange_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Exchange {
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+            trade: trade_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            taker_mint,
+            mint_stats: test_harness.mint_stats_address(),
+            recipient_payment_token_account,
+            initializer_receive_token_account,
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Exchange { amount: 20 }.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, open_trade_ix, exchange_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    // 20 of 50 at a price of 30 for 50 costs 12.
+    assert_eq!(
+        test_harness
+            .get_token_balance(&initializer_receive_token_account)
+            .await,
+        12
+    );
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.amount, 30);
+    assert_eq!(escrow_account.released_amount, 20);
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Initialized);
+    let trade = test_harness.get_account::<escrow::Trade>(&trade_pda).await.unwrap();
+    assert_eq!(trade.filled_amount, 20);
+    assert_eq!(trade.taker_amount, 18);
+
+    let cancel_ix = test_harness.cancel_ix();
+    process(&mut test_harness.context, &[cancel_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.initializer_token_account)
+            .await,
+        80
+    );
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Cancelled);
+}
+
+#[tokio::test]
+async fn test_small_fills_pay_the_original_price() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let (trade_pda, _) = Pubkey::find_program_address(
+        &[b"trade", escrow_state_pda.as_ref()],
+        &test_harness.program_id,
+    );
+
+    let taker_authority = Keypair::new();
+    let taker_mint = TestContext::create_mint(&mut test_harness.context, &taker_authority.pubkey()).await;
+    let recipient_payment_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &taker_mint,
+        &test_harness.recipient.pubkey(),
+        &taker_authority,
+        30,
+    )
+    .await;
+    let initializer_receive_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &taker_mint,
+        &test_harness.initializer.pubkey(),
+        &taker_authority,
+        0,
+    )
+    .await;
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let open_trade_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::OpenTrade {
+            initializer: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            taker_mint,
+            trade: trade_pda,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::OpenTrade { taker_amount: 30 }.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, open_trade_ix],
+        &[&test_harness.initializer],
+    )
+    .await
+    .unwrap();
+
+    let mint_stats = test_harness.mint_stats_address();
+    let exchange_ix = |amount: u64| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Exchange {
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+            trade: trade_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            taker_mint,
+            mint_stats,
+            recipient_payment_token_account,
+            initializer_receive_token_account,
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Exchange { amount }.data(),
+    };
+
+    // At 30 for 50, a single token costs 0.6, rounded up to 1 for the
+    // initializer.
+    process(&mut test_harness.context, &[exchange_ix(1)], &[&test_harness.recipient])
+        .await
+        .unwrap();
+    assert_eq!(
+        test_harness
+            .get_token_balance(&initializer_receive_token_account)
+            .await,
+        1
+    );
+
+    // Three fills of 5 cost 3 each, however much is left.
+    process(
+        &mut test_harness.context,
+        &[exchange_ix(5), exchange_ix(5), exchange_ix(5)],
+        &[&test_harness.recipient],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        test_harness
+            .get_token_balance(&initializer_receive_token_account)
+            .await,
+        10
+    );
+    let trade = test_harness.get_account::<escrow::Trade>(&trade_pda).await.unwrap();
+    assert_eq!(trade.filled_amount, 16);
+    assert_eq!(trade.taker_amount, 20);
+    assert_eq!(trade.original_amount, 50);
+    assert_eq!(trade.original_taker_amount, 30);
+
+    // The last fill pays the rest of the price, not its rounded share.
+    process(&mut test_harness.context, &[exchange_ix(34)], &[&test_harness.recipient])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&initializer_receive_token_account)
+            .await,
+        30
+    );
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        50
+    );
+    let trade = test_harness.get_account::<escrow::Trade>(&trade_pda).await.unwrap();
+    assert_eq!(trade.taker_amount, 0);
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Withdrawn);
+}
+
+#[tokio::test]
+async fn test_milestones_release_part_of_the_amount() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let milestone_pda = test_harness.milestone_address(0);
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let add_ix = test_harness.add_milestone_ix(0, 20);
+    let release_ix =
+        test_harness.release_milestone_ix(0, &test_harness.initializer.pubkey(), None);
+    process(
+        &mut test_harness.context,
+        &[init_ix, add_ix, release_ix.clone()],
+        &[&test_harness.initializer],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.amount, 30);
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Initialized);
+    let milestone = test_harness.get_account::<escrow::Milestone>(&milestone_pda).await.unwrap();
+    assert!(milestone.released);
+
+    // A milestone is only paid once.
+    test_harness.refresh_blockhash().await;
+    assert!(
+        process(&mut test_harness.context, &[release_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+
+    let recipient_account = test_harness
+        .get_account::<token::TokenAccount>(&test_harness.recipient_token_account)
+        .await
+        .unwrap();
+    assert_eq!(recipient_account.amount, 50);
+}
+
+#[tokio::test]
+async fn test_milestones_cannot_exceed_amount() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let first_ix = test_harness.add_milestone_ix(0, 30);
+    process(
+        &mut test_harness.context,
+        &[init_ix, first_ix],
+        &[&test_harness.initializer],
+    )
+    .await
+    .unwrap();
+
+    // 30 + 30 is more than the escrow holds.
+    let over_ix = test_harness.add_milestone_ix(1, 30);
+    assert!(
+        process(&mut test_harness.context, &[over_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    let second_ix = test_harness.add_milestone_ix(1, 20);
+    process(&mut test_harness.context, &[second_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.milestone_count, 2);
+    assert_eq!(escrow_account.milestone_total, 50);
+}
+
+#[tokio::test]
+async fn test_milestone_dispute_goes_to_its_own_arbiter() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let reviewer = Keypair::new();
+    let disputed_pda = test_harness.milestone_address(1);
+
+    let mut add_reviewed_ix = test_harness.add_milestone_ix(1, 20);
+    add_reviewed_ix.accounts = escrow::accounts::AddMilestone {
+        initializer: test_harness.initializer.pubkey(),
+        escrow_state: escrow_state_pda,
+        milestone: disputed_pda,
+        arbiter: Some(reviewer.pubkey()),
+        system_program: system_program::ID,
+    }
+    .to_account_metas(None);
+    let dispute_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::RaiseMilestoneDispute {
+            party: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            milestone: disputed_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::RaiseMilestoneDispute { reason_code: 2 }.data(),
+    };
+    let resolve_ix = |arbiter: Pubkey| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::ResolveMilestoneDispute {
+            arbiter,
+            escrow_state: escrow_state_pda,
+            milestone: disputed_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            recipient_deposit_token_account: test_harness.recipient_token_account,
+            initializer_refund_token_account: test_harness.initializer_token_account,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::ResolveMilestoneDispute {
+            release_to_recipient: false,
+        }
+        .data(),
+    };
+    let by_escrow_arbiter_ix = resolve_ix(test_harness.arbiter.pubkey());
+    let by_reviewer_ix = resolve_ix(reviewer.pubkey());
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let add_ix = test_harness.add_milestone_ix(0, 10);
+    process(
+        &mut test_harness.context,
+        &[init_ix, add_ix, add_reviewed_ix, dispute_ix],
+        &[&test_harness.initializer, &reviewer],
+    )
+    .await
+    .unwrap();
+    let milestone = test_harness.get_account::<escrow::Milestone>(&disputed_pda).await.unwrap();
+    assert_eq!(milestone.arbiter, reviewer.pubkey());
+    assert!(milestone.disputed);
+
+    // The other milestone carries on, but the escrow as a whole is held.
+    let initializer = test_harness.initializer.pubkey();
+    let release_ix = test_harness.release_milestone_ix(0, &initializer, None);
+    process(&mut test_harness.context, &[release_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    assert!(
+        process(&mut test_harness.context, std::slice::from_ref(&withdraw_ix), &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+
+    // Only the milestone's own arbiter rules on it.
+    assert!(
+        process(&mut test_harness.context, &[by_escrow_arbiter_ix], &[&test_harness.arbiter])
+            .await
+            .is_err()
+    );
+    process(&mut test_harness.context, &[by_reviewer_ix], &[&reviewer])
+        .await
+        .unwrap();
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.initializer_token_account)
+            .await,
+        70
+    );
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.amount, 20);
+    assert_eq!(escrow_account.disputed_milestones, 0);
+
+    test_harness.refresh_blockhash().await;
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        30
+    );
+}
+
+#[tokio::test]
+async fn test_milestone_release_waits_for_release_date() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let mut clock: Clock = test_harness.context.banks_client.get_sysvar().await.unwrap();
+    let release_not_before = clock.unix_timestamp + 50;
+
+    let init_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Initialize {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
+            mint: test_harness.mint,
+            initializer_deposit_token_account: test_harness.initializer_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            withdraw_cosigner: None,
+            recovery_authority: None,
+            recovery_token_account: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Initialize {
+            amount: 50,
+            timeout: 100,
+            terms_hash: [0; 32],
+            external_id: 0,
+            invoice_hash: [0; 32],
+            order_seeded: false,
+            release_not_before,
+            reject_freezable: false,
+            timeout_warning_window: 100,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+    let add_ix = test_harness.add_milestone_ix(0, 20);
+    process(
+        &mut test_harness.context,
+        &[init_ix, add_ix],
+        &[&test_harness.initializer],
+    )
+    .await
+    .unwrap();
+
+    let release_ix =
+        test_harness.release_milestone_ix(0, &test_harness.initializer.pubkey(), None);
+    assert!(
+        process(&mut test_harness.context, std::slice::from_ref(&release_ix), &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    clock.unix_timestamp = release_not_before;
+    test_harness.context.set_sysvar(&clock);
+    test_harness.refresh_blockhash().await;
+    process(&mut test_harness.context, &[release_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        20
+    );
+}
+
+#[tokio::test]
+async fn test_milestone_release_honours_settlement_guard() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let guard_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::EnableSettlementGuard {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::EnableSettlementGuard {}.data(),
+    };
+    let add_ix = test_harness.add_milestone_ix(0, 20);
+    process(
+        &mut test_harness.context,
+        &[init_ix, guard_ix, add_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    // Without the instructions sysvar the guard cannot be checked.
+    let initializer = test_harness.initializer.pubkey();
+    let unguarded_ix = test_harness.release_milestone_ix(0, &initializer, None);
+    assert!(
+        process(&mut test_harness.context, &[unguarded_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    // Any other program in the same transaction is rejected.
+    let release_ix = test_harness.release_milestone_ix(
+        0,
+        &initializer,
+        Some(solana_sdk::sysvar::instructions::ID),
+    );
+    let transfer_ix = solana_sdk::system_instruction::transfer(
+        &test_harness.context.payer.pubkey(),
+        &test_harness.recipient.pubkey(),
+        1,
+    );
+    assert!(
+        process(
+            &mut test_harness.context,
+            &[transfer_ix, release_ix.clone()],
+            &[&test_harness.initializer],
+        )
+        .await
+        .is_err()
+    );
+
+    process(&mut test_harness.context, &[release_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+    let milestone = test_harness
+        .get_account::<escrow::Milestone>(&test_harness.milestone_address(0))
+        .await
+        .unwrap();
+    assert!(milestone.released);
+}
+
+#[tokio::test]
+async fn test_cancel_honours_settlement_guard() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let guard_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::EnableSettlementGuard {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::EnableSettlementGuard {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, guard_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    // Refunds to the initializer are guarded like payouts to the recipient.
+    let unguarded_ix = test_harness.cancel_ix();
+    assert!(
+        process(&mut test_harness.context, &[unguarded_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    let cancel_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Cancel {
+            initializer: test_harness.initializer.pubkey(),
+            initializer_refund_token_account: test_harness.initializer_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            token_program: token::ID,
+            instructions_sysvar: Some(solana_sdk::sysvar::instructions::ID),
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Cancel {}.data(),
+    };
+    let transfer_ix = solana_sdk::system_instruction::transfer(
+        &test_harness.context.payer.pubkey(),
+        &test_harness.recipient.pubkey(),
+        1,
+    );
+    assert!(
+        process(
+            &mut test_harness.context,
+            &[transfer_ix, cancel_ix.clone()],
+            &[&test_harness.initializer],
+        )
+        .await
+        .is_err()
+    );
+
+    process(&mut test_harness.context, &[cancel_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Cancelled);
+}
+
+#[tokio::test]
+async fn test_milestone_release_holds_back_retainage() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let create_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::CreateEscrow {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            arbiter: Some(test_harness.arbiter.pubkey()),
+            mint: test_harness.mint,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            governance_proposal: None,
+            withdraw_cosigner: None,
+            governance_program: None,
+            rent_collector: None,
+            arbiter_registrar: None,
+            template: None,
+            initializer_profile: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::CreateEscrow {
+            amount: 50,
+            timeout: 100,
+            terms_hash: [0; 32],
+            funding_period: 100,
+            crowdfunded: false,
+            min_goal: 50,
+            retainage_bps: 1_000,
+            warranty_period: 100,
+            external_id: 0,
+            invoice_hash: [0; 32],
+            release_not_before: 0,
+            reject_freezable: false,
+            timeout_warning_window: 100,
+            dispute_window: 0,
+            required_qualifications: 0,
+            max_arbiter_fee_bps: 0,
+        }
+        .data(),
+    };
+    let accept_ix = test_harness.accept_arbiter_ix();
+    let fund_ix = test_harness.fund_escrow_ix(50);
+    let add_ix = test_harness.add_milestone_ix(0, 20);
+    let release_ix =
+        test_harness.release_milestone_ix(0, &test_harness.initializer.pubkey(), None);
+    process(
+        &mut test_harness.context,
+        &[create_ix, accept_ix, fund_ix, add_ix, release_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    // 10% of the 20-token milestone stays in the vault.
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        18
+    );
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.amount, 30);
+    assert_eq!(escrow_account.retained_amount, 2);
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 32);
+
+    // Withdrawing the rest adds its own retainage to what is held back.
+    let withdraw_ix = test_harness.withdraw_ix(None);
+    process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        45
+    );
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.retained_amount, 5);
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 5);
+}
+
+#[tokio::test]
+async fn test_arbiter_releases_milestones_only_in_dispute() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, _) = test_harness.escrow_addresses();
+    let arbiter = test_harness.arbiter.pubkey();
+    let initializer = test_harness.initializer.pubkey();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let accept_ix = test_harness.accept_arbiter_ix();
+    let first_ix = test_harness.add_milestone_ix(0, 20);
+    let second_ix = test_harness.add_milestone_ix(1, 10);
+    process(
+        &mut test_harness.context,
+        &[init_ix, accept_ix, first_ix, second_ix],
+        &[&test_harness.initializer, &test_harness.arbiter],
+    )
+    .await
+    .unwrap();
+
+    // The arbiter cannot move funds on an escrow nobody disputes.
+    let release_ix = test_harness.release_milestone_ix(0, &arbiter, None);
+    assert!(
+        process(&mut test_harness.context, std::slice::from_ref(&release_ix), &[&test_harness.arbiter])
+            .await
+            .is_err()
+    );
+
+    let dispute_ix = test_harness.raise_dispute_ix();
+    process(&mut test_harness.context, &[dispute_ix], &[&test_harness.recipient])
+        .await
+        .unwrap();
+
+    // Once disputed, the initializer can no longer release on their own.
+    let initializer_release_ix = test_harness.release_milestone_ix(1, &initializer, None);
+    assert!(
+        process(
+            &mut test_harness.context,
+            &[initializer_release_ix],
+            &[&test_harness.initializer],
+        )
+        .await
+        .is_err()
+    );
+
+    test_harness.refresh_blockhash().await;
+    process(&mut test_harness.context, &[release_ix], &[&test_harness.arbiter])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.recipient_token_account)
+            .await,
+        20
+    );
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.amount, 30);
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Disputed);
+}
+
+#[tokio::test]
+async fn test_renew_carries_deposit_to_successor() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let order_id: u64 = 9;
+    let (successor_pda, _) = Pubkey::find_program_address(
+        &[
+            b"escrow",
+            test_harness.initializer.pubkey().as_ref(),
+            &order_id.to_le_bytes(),
+        ],
+        &test_harness.program_id,
+    );
+    let successor_vault = get_associated_token_address(&successor_pda, &test_harness.mint);
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let renew_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Renew {
+            initializer: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            initializer_token_account: test_harness.initializer_token_account,
+            successor: successor_pda,
+            successor_vault,
+            mint_stats: test_harness.mint_stats_address(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Renew {
+            amount: 70,
+            external_id: order_id,
+        }
+        .data(),
+    };
+    // A heartbeat stretches the old escrow's duration; the successor should
+    // still start from the configured 100 seconds.
+    let enable_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::EnableHeartbeat {
+            initializer: test_harness.initializer.pubkey(),
+            recipient: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::EnableHeartbeat {
+            increment: 150,
+            max_duration: 200,
+        }
+        .data(),
+    };
+    let heartbeat_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Heartbeat {
+            party: test_harness.recipient.pubkey(),
+            escrow_state: escrow_state_pda,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Heartbeat {}.data(),
+    };
+    let accept_ix = test_harness.accept_arbiter_ix();
+    process(
+        &mut test_harness.context,
+        &[init_ix, enable_ix, heartbeat_ix, accept_ix, renew_ix],
+        &[
+            &test_harness.initializer,
+            &test_harness.recipient,
+            &test_harness.arbiter,
+        ],
+    )
+    .await
+    .unwrap();
+
+    // The 50 already deposited move over; only the extra 20 are new.
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 0);
+    assert_eq!(test_harness.get_token_balance(&successor_vault).await, 70);
+    assert_eq!(
+        test_harness
+            .get_token_balance(&test_harness.initializer_token_account)
+            .await,
+        30
+    );
+
+    let previous = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(previous.status, escrow::EscrowStatus::Cancelled);
+    assert!(previous.duration > 100);
+    let successor = test_harness.get_account::<escrow::Escrow>(&successor_pda).await.unwrap();
+    assert_eq!(successor.status, escrow::EscrowStatus::Initialized);
+    assert_eq!(successor.amount, 70);
+    assert_eq!(successor.duration, 100);
+    assert_eq!(successor.configured_duration, 100);
+    assert_eq!(successor.recipient, test_harness.recipient.pubkey());
+    assert_eq!(successor.previous_escrow, escrow_state_pda);
+    assert!(successor.order_seeded);
+    // The arbiter accepted the old escrow, not this one.
+    assert!(!successor.arbiter_accepted);
+    assert!(successor.arbiter_acceptance_deadline > 0);
+
+    // Only the 20 new tokens count as new volume.
+    let mint_stats = test_harness
+        .get_account::<escrow::MintStats>(&test_harness.mint_stats_address())
+        .await
+        .unwrap();
+    assert_eq!(mint_stats.active_escrows, 1);
+    assert_eq!(mint_stats.total_locked, 70);
+    assert_eq!(mint_stats.lifetime_volume, 70);
+}
+
+#[tokio::test]
+async fn test_close_escrow_pays_rent_to_rent_collector() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+    let platform = Pubkey::new_unique();
+
+    let mut init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    init_ix.accounts = escrow::accounts::Initialize {
+        initializer: test_harness.initializer.pubkey(),
+        recipient: test_harness.recipient.pubkey(),
+        arbiter: Some(test_harness.arbiter.pubkey()),
+        mint: test_harness.mint,
+        initializer_deposit_token_account: test_harness.initializer_token_account,
+        escrow_state: escrow_state_pda,
+        vault: vault_pda,
+        mint_stats: test_harness.mint_stats_address(),
+        system_program: system_program::ID,
+        token_program: token::ID,
+        associated_token_program: associated_token::ID,
+        withdraw_cosigner: None,
+        recovery_authority: None,
+        recovery_token_account: None,
+        rent_collector: Some(platform),
+        arbiter_registrar: None,
+        template: None,
+        initializer_profile: None,
+    }
+    .to_account_metas(None);
+    let cancel_ix = test_harness.cancel_ix();
+    process(
+        &mut test_harness.context,
+        &[init_ix, cancel_ix],
+        &[&test_harness.initializer],
+    )
+    .await
+    .unwrap();
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.rent_collector, platform);
+
+    let close_ix = |rent_collector: Pubkey| Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::CloseEscrow {
+            initializer: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            rent_collector,
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::CloseEscrow {}.data(),
+    };
+    let to_initializer_ix = close_ix(test_harness.initializer.pubkey());
+    let to_platform_ix = close_ix(platform);
+
+    // The rent no longer goes back to the initializer.
+    assert!(
+        process(&mut test_harness.context, &[to_initializer_ix], &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    let rent = test_harness
+        .context
+        .banks_client
+        .get_account(escrow_state_pda)
+        .await
+        .unwrap()
+        .unwrap()
+        .lamports
+        + test_harness
+            .context
+            .banks_client
+            .get_account(vault_pda)
+            .await
+            .unwrap()
+            .unwrap()
+            .lamports;
+    process(&mut test_harness.context, &[to_platform_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+    let platform_balance = test_harness
+        .context
+        .banks_client
+        .get_balance(platform)
+        .await
+        .unwrap();
+    assert_eq!(platform_balance, rent);
+}
+
+#[tokio::test]
+async fn test_close_escrow_after_cancel() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let cancel_ix = test_harness.cancel_ix();
+    let close_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::CloseEscrow {
+            initializer: test_harness.initializer.pubkey(),
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            token_program: token::ID,
+            rent_collector: test_harness.initializer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::CloseEscrow {}.data(),
+    };
+
+    // An escrow that still holds its deposit cannot be closed.
+    let init_and_close = [init_ix, close_ix.clone()];
+    assert!(
+        process(&mut test_harness.context, &init_and_close, &[&test_harness.initializer])
+            .await
+            .is_err()
+    );
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    process(
+        &mut test_harness.context,
+        &[init_ix, cancel_ix, close_ix],
+        &[&test_harness.initializer],
+    )
+    .await
+    .unwrap();
+
+    for address in [escrow_state_pda, vault_pda] {
+        assert!(test_harness
+            .context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_recipient_declines_escrow() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let decline_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Decline {
+            recipient: test_harness.recipient.pubkey(),
+            initializer_refund_token_account: test_harness.initializer_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Decline { reason_code: 2 }.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, decline_ix],
+        &[&test_harness.initializer, &test_harness.recipient],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Refunded);
+    let initializer_account = test_harness
+        .get_account::<token::TokenAccount>(&test_harness.initializer_token_account)
+        .await
+        .unwrap();
+    assert_eq!(initializer_account.amount, 100);
+}
+
+#[tokio::test]
+async fn test_recipient_cannot_decline_after_a_milestone() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    let add_ix = test_harness.add_milestone_ix(0, 20);
+    let release_ix =
+        test_harness.release_milestone_ix(0, &test_harness.initializer.pubkey(), None);
+    process(
+        &mut test_harness.context,
+        &[init_ix, add_ix, release_ix],
+        &[&test_harness.initializer],
+    )
+    .await
+    .unwrap();
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.released_amount, 20);
+
+    // Taking the first milestone accepted the escrow.
+    let decline_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Decline {
+            recipient: test_harness.recipient.pubkey(),
+            initializer_refund_token_account: test_harness.initializer_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Decline { reason_code: 2 }.data(),
+    };
+    assert!(
+        process(&mut test_harness.context, &[decline_ix], &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+
+    let escrow_account = test_harness.get_account::<escrow::Escrow>(&escrow_state_pda).await.unwrap();
+    assert_eq!(escrow_account.status, escrow::EscrowStatus::Initialized);
+    assert_eq!(test_harness.get_token_balance(&vault_pda).await, 30);
+}
+
+#[tokio::test]
+async fn test_payouts_require_the_escrow_mint() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    process(&mut test_harness.context, &[init_ix], &[&test_harness.initializer])
+        .await
+        .unwrap();
+
+    let other_authority = Keypair::new();
+    let other_mint = TestContext::create_mint(&mut test_harness.context, &other_authority.pubkey()).await;
+    let other_token_account = TestContext::create_token_account(
+        &mut test_harness.context,
+        &other_mint,
+        &test_harness.recipient.pubkey(),
+        &other_authority,
+        0,
+    )
+    .await;
+
+    let withdraw_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::Withdraw {
+            recipient: test_harness.recipient.pubkey(),
+            recipient_deposit_token_account: other_token_account,
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            mint_stats: test_harness.mint_stats_address(),
+            token_program: token::ID,
+            governance_proposal: None,
+            withdraw_cosigner: None,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::Withdraw {}.data(),
+    };
+    assert!(
+        process(&mut test_harness.context, &[withdraw_ix], &[&test_harness.recipient])
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_sweep_surplus_returns_donations_to_initializer() {
+    let mut test_harness = TestContext::new().await;
+    let (escrow_state_pda, vault_pda) = test_harness.escrow_addresses();
+
+    let init_ix = test_harness.initialize_ix(50, 100, [0; 32]);
+    // Tokens minted straight into the vault bypass the escrow's accounting.
+    let donation_ix = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &test_harness.mint,
+        &vault_pda,
+        &test_harness.mint_authority.pubkey(),
+        &[],
+        7,
+    )
+    .unwrap();
+    let sweep_ix = Instruction {
+        program_id: test_harness.program_id,
+        accounts: escrow::accounts::SweepSurplus {
+            escrow_state: escrow_state_pda,
+            vault: vault_pda,
+            mint: test_harness.mint,
+            initializer_refund_token_account: test_harness.initializer_token_account,
+            token_program: token::ID,
+            instructions_sysvar: None,
+        }
+        .to_account_metas(None),
+        data: escrow::instruction::SweepSurplus {}.data(),
+    };
+    process(
+        &mut test_harness.context,
+        &[init_ix, donation_ix, sweep_ix],
+        &[&test_harness.initializer, &test_harness.mint_authority],
+    )
+    .await
+    .unwrap();
+
+    let vault_account = test_harness.get_account::<token::TokenAccount>(&vault_pda).await.unwrap();
+    assert_eq!(vault_account.amount, 50);
+    let initializer_account = test_harness
+        .get_account::<token::TokenAccount>(&test_harness.initializer_token_account)
+        .await
+        .unwrap();
+    assert_eq!(initializer_account.amount, 57);
 }